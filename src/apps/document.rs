@@ -0,0 +1,303 @@
+use pest::Parser;
+use std::fmt::{Display, Formatter};
+
+/// A single line of a `mimeapps.list`, along with its original text
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Line {
+    /// `[Section Name]`
+    Section { name: String, raw: String },
+    /// `key=value`
+    Entry {
+        key:   String,
+        value: String,
+        raw:   String,
+    },
+    /// Comments, blank lines and anything else that is passed through as is
+    Other(String),
+}
+
+impl Line {
+    fn parse(raw: &str) -> Self {
+        let content = raw.strip_suffix('\r').unwrap_or(raw);
+        if content.trim_start().starts_with('#') {
+            return Self::Other(raw.to_owned());
+        }
+        let Some(pair) = <Document as Parser<Rule>>::parse(Rule::line, content)
+            .ok()
+            .and_then(|mut pairs| pairs.next()?.into_inner().next())
+        else {
+            return Self::Other(raw.to_owned());
+        };
+
+        match pair.as_rule() {
+            Rule::section => Self::Section {
+                name: pair.into_inner().concat(),
+                raw:  raw.to_owned(),
+            },
+            _ => {
+                let mut inner_rules = pair.into_inner(); // { name ~ "=" ~ value }
+                Self::Entry {
+                    key:   inner_rules.next().unwrap().as_str().trim().to_owned(),
+                    value: inner_rules.next().unwrap().as_str().trim().to_owned(),
+                    raw:   raw.to_owned(),
+                }
+            },
+        }
+    }
+
+    fn raw(&self) -> &str {
+        match self {
+            Self::Section { raw, .. } | Self::Entry { raw, .. } | Self::Other(raw) => raw,
+        }
+    }
+}
+
+/// A `mimeapps.list` kept line by line, so that everything which is not
+/// explicitly modified is written back exactly as it was read
+#[derive(Debug, Default, Clone, pest_derive::Parser)]
+#[grammar = "common/ini.pest"]
+pub(crate) struct Document {
+    lines:           Vec<Line>,
+    missing_newline: bool,
+    /// Whether lines end with `\r\n`
+    crlf:            bool,
+}
+
+impl Document {
+    /// Parse a `mimeapps.list` one line at a time. Lines that are neither a
+    /// section header nor an entry are kept as they are.
+    pub(crate) fn parse(raw: &str) -> Self {
+        let mut lines = raw.split('\n').map(Line::parse).collect::<Vec<_>>();
+        let missing_newline = !raw.is_empty() && !raw.ends_with('\n');
+        if !missing_newline {
+            lines.pop();
+        }
+
+        Self {
+            lines,
+            missing_newline,
+            crlf: raw.contains("\r\n"),
+        }
+    }
+
+    /// What ends the lines of the document before the `\n`, so that new lines
+    /// match the existing ones
+    fn cr(&self) -> &'static str {
+        if self.crlf {
+            "\r"
+        } else {
+            ""
+        }
+    }
+
+    /// Every `(section, key, value)` triple, in the order they appear
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (&str, &str, &str)> {
+        let mut section = "";
+
+        self.lines.iter().filter_map(move |line| match line {
+            Line::Section { name, .. } => {
+                section = name;
+                None
+            },
+            Line::Entry { key, value, .. } => Some((section, key.as_str(), value.as_str())),
+            Line::Other(_) => None,
+        })
+    }
+
+    /// The `;`-separated values of `key`, including ones handlr does not
+    /// understand
+    pub(crate) fn values(&self, section: &str, key: &str) -> Vec<String> {
+        self.entries()
+            .filter(|(s, k, _)| *s == section && k.eq_ignore_ascii_case(key))
+            .last()
+            .map(|(_, _, value)| {
+                value
+                    .split(';')
+                    .filter(|v| !v.is_empty())
+                    .map(ToOwned::to_owned)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Indices of the entries for `key` in `section`
+    fn find(&self, section: &str, key: &str) -> Vec<usize> {
+        let mut current = "";
+        let mut found = vec![];
+
+        for (idx, line) in self.lines.iter().enumerate() {
+            match line {
+                Line::Section { name, .. } => current = name,
+                Line::Entry { key: k, .. } if current == section && k.eq_ignore_ascii_case(key) =>
+                    found.push(idx),
+                _ => {},
+            }
+        }
+
+        found
+    }
+
+    /// Where a new entry of `section` goes: right after its last entry or
+    /// header, if the section exists
+    fn insertion_point(&self, section: &str) -> Option<usize> {
+        let mut current = "";
+        let mut point = None;
+
+        for (idx, line) in self.lines.iter().enumerate() {
+            match line {
+                Line::Section { name, .. } => {
+                    current = name;
+                    if current == section {
+                        point = Some(idx + 1);
+                    }
+                },
+                Line::Entry { .. } if current == section => point = Some(idx + 1),
+                _ => {},
+            }
+        }
+
+        point
+    }
+
+    /// Replace the values of `key`, removing the entry if there are none left.
+    /// Every other line is left untouched.
+    pub(crate) fn set(&mut self, section: &str, key: &str, values: &[String]) {
+        let existing = self.find(section, key);
+
+        if values.is_empty() {
+            for idx in existing.into_iter().rev() {
+                self.lines.remove(idx);
+            }
+            return;
+        }
+
+        let value = format!("{};", values.join(";"));
+        let entry = Line::Entry {
+            key: key.to_owned(),
+            raw: format!("{key}={value}{}", self.cr()),
+            value,
+        };
+
+        if let Some((&last, duplicates)) = existing.split_last() {
+            self.lines[last] = entry;
+            for &idx in duplicates.iter().rev() {
+                self.lines.remove(idx);
+            }
+        } else if let Some(idx) = self.insertion_point(section) {
+            self.lines.insert(idx, entry);
+        } else {
+            if self
+                .lines
+                .last()
+                .is_some_and(|l| !l.raw().trim().is_empty())
+            {
+                self.lines.push(Line::Other(self.cr().to_owned()));
+            }
+            self.lines.push(Line::Section {
+                name: section.to_owned(),
+                raw:  format!("[{section}]{}", self.cr()),
+            });
+            self.lines.push(entry);
+        }
+    }
+}
+
+impl Display for Document {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (idx, line) in self.lines.iter().enumerate() {
+            f.write_str(line.raw())?;
+            if idx + 1 < self.lines.len() || !self.missing_newline {
+                f.write_str("\n")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RAW: &str = "# managed by dotfiles\n[Default Applications]\ntext/plain=nvim.desktop;\n# \
+                       browsers\nx-scheme-handler/https=gone.desktop;firefox.desktop;\n\n[Some \
+                       Other Section]\nfoo=bar\n";
+
+    #[test]
+    fn lossless_round_trip() {
+        let mut doc = Document::parse(RAW);
+        assert_eq!(doc.to_string(), RAW);
+
+        doc.set("Default Applications", "text/plain", &[
+            "gedit.desktop".into()
+        ]);
+        assert_eq!(
+            doc.to_string(),
+            RAW.replace("text/plain=nvim.desktop;", "text/plain=gedit.desktop;")
+        );
+
+        doc.set("Default Applications", "text/html", &[
+            "brave.desktop".into()
+        ]);
+        assert_eq!(
+            doc.to_string(),
+            RAW.replace("text/plain=nvim.desktop;", "text/plain=gedit.desktop;")
+                .replace(
+                    "firefox.desktop;\n",
+                    "firefox.desktop;\ntext/html=brave.desktop;\n"
+                )
+        );
+
+        doc.set("Default Applications", "text/html", &[]);
+        doc.set("Default Applications", "text/plain", &[
+            "nvim.desktop".into()
+        ]);
+        assert_eq!(doc.to_string(), RAW);
+
+        doc.set("Added Associations", "image/png", &["feh.desktop".into()]);
+        assert_eq!(
+            doc.to_string(),
+            format!("{RAW}\n[Added Associations]\nimage/png=feh.desktop;\n")
+        );
+    }
+
+    #[test]
+    fn unusual_lines() {
+        // Empty values, whitespace only lines, dotted section names and lines
+        // that are neither are all kept
+        let raw = "[X-KDE.Group]\nkey=\n \t\n[Default Applications]\ntext/plain=\n\tgarbage\n[\n";
+        let mut doc = Document::parse(raw);
+        assert_eq!(doc.to_string(), raw);
+        assert_eq!(doc.entries().collect::<Vec<_>>(), vec![
+            ("X-KDE.Group", "key", ""),
+            ("Default Applications", "text/plain", "")
+        ]);
+
+        doc.set("Default Applications", "text/plain", &[
+            "nvim.desktop".into()
+        ]);
+        assert_eq!(
+            doc.to_string(),
+            raw.replace("text/plain=\n", "text/plain=nvim.desktop;\n")
+        );
+
+        // New lines end like the existing ones
+        let crlf = RAW.replace('\n', "\r\n");
+        let mut doc = Document::parse(&crlf);
+        assert_eq!(doc.to_string(), crlf);
+        assert_eq!(doc.values("Default Applications", "text/plain"), vec![
+            "nvim.desktop"
+        ]);
+
+        doc.set("Default Applications", "text/plain", &[
+            "gedit.desktop".into()
+        ]);
+        doc.set("Added Associations", "image/png", &["feh.desktop".into()]);
+        assert_eq!(
+            doc.to_string(),
+            format!("{RAW}\n[Added Associations]\nimage/png=feh.desktop;\n")
+                .replace("text/plain=nvim.desktop;", "text/plain=gedit.desktop;")
+                .replace('\n', "\r\n")
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use crate::{apps::Document, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt::{Display, Formatter},
+    io::Write,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// How a single `mimeapps.list` key changed
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct KeyChange {
+    pub(crate) section: String,
+    pub(crate) mime:    String,
+    pub(crate) before:  Vec<String>,
+    pub(crate) after:   Vec<String>,
+}
+
+impl Display for KeyChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        use colored::Colorize;
+
+        let (before, after) = (self.before.join(";"), self.after.join(";"));
+        match (self.before.is_empty(), self.after.is_empty()) {
+            (true, _) => write!(
+                f,
+                "{} [{}] {}: {}",
+                "+".green().bold(),
+                self.section,
+                self.mime,
+                after.green()
+            ),
+            (_, true) => write!(
+                f,
+                "{} [{}] {}: {}",
+                "-".red().bold(),
+                self.section,
+                self.mime,
+                before.red()
+            ),
+            _ => write!(
+                f,
+                "{} [{}] {}: {} -> {}",
+                "~".yellow().bold(),
+                self.section,
+                self.mime,
+                before.red(),
+                after.green()
+            ),
+        }
+    }
+}
+
+/// One journaled write of the user's `mimeapps.list`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Change {
+    pub(crate) id:        u64,
+    /// Seconds since the unix epoch
+    pub(crate) timestamp: u64,
+    pub(crate) command:   String,
+    pub(crate) changes:   Vec<KeyChange>,
+    /// Ids of the changes this one rolled back
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub(crate) reverts:   Vec<u64>,
+}
+
+impl Change {
+    pub(crate) fn time(&self) -> String {
+        humantime::format_rfc3339_seconds(UNIX_EPOCH + Duration::from_secs(self.timestamp))
+            .to_string()
+    }
+}
+
+/// The journal of changes kept in `$XDG_STATE_HOME/handlr/history.jsonl`
+#[derive(Debug, Default, Clone)]
+pub(crate) struct History(pub(crate) Vec<Change>);
+
+impl History {
+    pub(crate) fn path() -> Result<PathBuf> {
+        Ok(xdg::BaseDirectories::with_prefix("handlr")?.place_state_file("history.jsonl")?)
+    }
+
+    pub(crate) fn read() -> Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        Ok(Self(
+            std::fs::read_to_string(path)?
+                .lines()
+                .filter(|l| !l.trim().is_empty())
+                .map(serde_json::from_str)
+                .collect::<Result<Vec<_>, _>>()?,
+        ))
+    }
+
+    /// Append a change to the journal, unless nothing actually changed
+    pub(crate) fn record(changes: Vec<KeyChange>, reverts: Vec<u64>) -> Result<()> {
+        use itertools::Itertools;
+
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        let change = Change {
+            id: Self::read()?.0.last().map_or(1, |c| c.id + 1),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            command: std::env::args().skip(1).join(" "),
+            changes,
+            reverts,
+        };
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(Self::path()?)?;
+        writeln!(file, "{}", serde_json::to_string(&change)?)?;
+
+        Ok(())
+    }
+
+    pub(crate) fn print(&self) {
+        use itertools::Itertools;
+
+        let reverted = self.reverted();
+        let mut rows = vec![];
+
+        for change in &self.0 {
+            let command = if reverted.contains(&change.id) {
+                format!("{} (undone)", change.command)
+            } else {
+                change.command.clone()
+            };
+
+            for (idx, key) in change.changes.iter().enumerate() {
+                let (id, time, command) = if idx == 0 {
+                    (change.id.to_string(), change.time(), command.clone())
+                } else {
+                    Default::default()
+                };
+
+                rows.push(vec![
+                    id,
+                    time,
+                    command,
+                    key.section.clone(),
+                    key.mime.clone(),
+                    format!(
+                        "{} -> {}",
+                        key.before.iter().join(";"),
+                        key.after.iter().join(";")
+                    ),
+                ]);
+            }
+        }
+
+        ascii_table::AsciiTable {
+            max_width: usize::MAX,
+            ..ascii_table::AsciiTable::default()
+        }
+        .print(rows);
+    }
+
+    /// Ids of changes that have already been rolled back
+    pub(crate) fn reverted(&self) -> HashSet<u64> {
+        self.0
+            .iter()
+            .flat_map(|c| c.reverts.iter().copied())
+            .collect()
+    }
+
+    /// Changes that are still in effect and can be rolled back, newest first
+    fn undoable(&self) -> impl Iterator<Item = &Change> {
+        let reverted = self.reverted();
+        self.0
+            .iter()
+            .rev()
+            .filter(move |c| c.reverts.is_empty() && !reverted.contains(&c.id))
+    }
+
+    /// The last `count` changes that are still in effect, newest first
+    pub(crate) fn last_changes(&self, count: usize) -> Vec<Change> {
+        self.undoable().take(count).cloned().collect()
+    }
+
+    /// The changes still in effect that were made after the change with the
+    /// given id, newest first
+    pub(crate) fn changes_after(&self, id: u64) -> Result<Vec<Change>> {
+        if !self.0.iter().any(|c| c.id == id) {
+            return Err(Error::UnknownChange(id));
+        }
+
+        Ok(self.undoable().take_while(|c| c.id > id).cloned().collect())
+    }
+}
+
+/// Every key whose values differ between two versions of a `mimeapps.list`
+pub(crate) fn diff(before: &Document, after: &Document) -> Vec<KeyChange> {
+    before
+        .entries()
+        .chain(after.entries())
+        .map(|(section, key, _)| (section, key))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter_map(|(section, key)| {
+            let (b, a) = (before.values(section, key), after.values(section, key));
+            (b != a).then(|| KeyChange {
+                section: section.to_owned(),
+                mime:    key.to_owned(),
+                before:  b,
+                after:   a,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A journal entry setting `text/plain` from `before` to `after`
+    fn change(id: u64, before: &str, after: &str, reverts: Vec<u64>) -> Change {
+        let values = |v: &str| v.split_terminator(';').map(ToOwned::to_owned).collect();
+        Change {
+            id,
+            timestamp: 0,
+            command: String::new(),
+            changes: vec![KeyChange {
+                section: "Default Applications".into(),
+                mime:    "text/plain".into(),
+                before:  values(before),
+                after:   values(after),
+            }],
+            reverts,
+        }
+    }
+
+    /// Five writes: three changes, an undo of the third and another change
+    fn journal() -> History {
+        History(vec![
+            change(1, "", "nvim.desktop", vec![]),
+            change(2, "nvim.desktop", "gedit.desktop", vec![]),
+            change(3, "gedit.desktop", "kate.desktop", vec![]),
+            change(4, "kate.desktop", "gedit.desktop", vec![3]),
+            change(5, "gedit.desktop", "code.desktop", vec![]),
+        ])
+    }
+
+    #[test]
+    fn changes_to_undo() {
+        let ids = |changes: Vec<Change>| changes.iter().map(|c| c.id).collect::<Vec<_>>();
+        let history = journal();
+
+        // Undone changes and the undos themselves are skipped
+        assert_eq!(ids(history.last_changes(1)), vec![5]);
+        assert_eq!(ids(history.last_changes(2)), vec![5, 2]);
+        assert_eq!(ids(history.last_changes(10)), vec![5, 2, 1]);
+
+        assert_eq!(ids(history.changes_after(1).unwrap()), vec![5, 2]);
+        assert_eq!(ids(history.changes_after(3).unwrap()), vec![5]);
+        assert!(history.changes_after(5).unwrap().is_empty());
+        assert!(matches!(
+            history.changes_after(42),
+            Err(Error::UnknownChange(42))
+        ));
+    }
+
+    #[test]
+    fn diff_documents() {
+        let before = Document::parse(
+            "[Default Applications]\ntext/plain=nvim.desktop;\nimage/png=feh.desktop;\n",
+        );
+        let after = Document::parse(
+            "[Default Applications]\ntext/plain=gedit.desktop;\nimage/png=feh.desktop;\n\n[Added \
+             Associations]\ntext/html=brave.desktop;\n",
+        );
+
+        assert_eq!(diff(&before, &after), vec![
+            KeyChange {
+                section: "Added Associations".into(),
+                mime:    "text/html".into(),
+                before:  vec![],
+                after:   vec!["brave.desktop".into()],
+            },
+            KeyChange {
+                section: "Default Applications".into(),
+                mime:    "text/plain".into(),
+                before:  vec!["nvim.desktop".into()],
+                after:   vec!["gedit.desktop".into()],
+            },
+        ]);
+        assert!(diff(&after, &after).is_empty());
+    }
+}
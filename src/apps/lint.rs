@@ -0,0 +1,331 @@
+use crate::{
+    apps::{Document, Section},
+    common::Handler,
+};
+use mime::Mime;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// The kinds of problems `handlr lint` reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Kind {
+    /// A handler that is not installed or has a broken desktop entry
+    DanglingHandler,
+    /// The same handler listed twice for a mime
+    DuplicateHandler,
+    /// The same mime listed twice in a section
+    DuplicateKey,
+    /// A handler whose `MimeType=` does not declare the mime
+    UndeclaredMime,
+    /// A key that is not a valid mime
+    MalformedMime,
+    /// An entry that never takes effect because a higher precedence file sets
+    /// the same mime
+    Shadowed,
+    /// An entry that has the same handlers as the wildcard covering it
+    Redundant,
+}
+
+impl Kind {
+    /// Whether `--fix` can repair the problem without changing which handler
+    /// is picked. Redundant entries are not fixable when removing them would
+    /// let another file take over.
+    pub(crate) fn fixable(self) -> bool {
+        matches!(
+            self,
+            Self::DanglingHandler | Self::DuplicateHandler | Self::DuplicateKey | Self::Redundant
+        )
+    }
+}
+
+/// A single problem found in a `mimeapps.list`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct Lint {
+    pub(crate) kind:    Kind,
+    pub(crate) path:    PathBuf,
+    pub(crate) section: String,
+    pub(crate) mime:    String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) handler: Option<String>,
+    pub(crate) message: String,
+    pub(crate) fixable: bool,
+}
+
+impl Display for Lint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        use colored::Colorize;
+
+        write!(
+            f,
+            "{}: [{}] {}: {}",
+            self.path.display(),
+            self.section,
+            self.mime,
+            self.message
+        )?;
+        if self.fixable {
+            write!(f, " {}", "(fixable)".green())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Find problems in the user's `mimeapps.list`. `higher` holds the documents
+/// that take precedence over it and `lower` the ones it takes precedence over.
+pub(crate) fn check(
+    path: &Path,
+    document: &Document,
+    higher: &[(&Path, &Document)],
+    lower: &[(&Path, &Document)],
+) -> Vec<Lint> {
+    use itertools::Itertools;
+
+    let mut lints = vec![];
+    let lint =
+        |kind: Kind, section: &str, mime: &str, handler: Option<&str>, message: String| Lint {
+            kind,
+            path: path.to_owned(),
+            section: section.to_owned(),
+            mime: mime.to_owned(),
+            handler: handler.map(ToOwned::to_owned),
+            message,
+            fixable: kind.fixable(),
+        };
+
+    let entries = document
+        .entries()
+        .filter(|(section, ..)| Section::from_name(section).is_some())
+        .collect::<Vec<_>>();
+
+    let mut seen = HashMap::<(&str, &str), usize>::new();
+    for (section, key, _) in &entries {
+        *seen.entry((section, key)).or_default() += 1;
+    }
+
+    for ((section, key), count) in seen.iter().sorted() {
+        if *count > 1 {
+            lints.push(lint(
+                Kind::DuplicateKey,
+                section,
+                key,
+                None,
+                format!("listed {count} times, only the last one is used"),
+            ));
+        }
+    }
+
+    for (section, key) in seen.keys().sorted() {
+        let values = document.values(section, key);
+
+        let mime = match Mime::from_str(key) {
+            Ok(mime) if !mime.subtype().as_str().is_empty() => mime,
+            _ => {
+                lints.push(lint(
+                    Kind::MalformedMime,
+                    section,
+                    key,
+                    None,
+                    "malformed mime".into(),
+                ));
+                continue;
+            },
+        };
+
+        for handler in values.iter().duplicates() {
+            lints.push(lint(
+                Kind::DuplicateHandler,
+                section,
+                key,
+                Some(handler),
+                format!("{handler} is listed more than once"),
+            ));
+        }
+
+        if Section::from_name(section) == Some(Section::DefaultApplications) {
+            if let Some((shadowing, _)) = higher
+                .iter()
+                .find(|(_, doc)| !doc.values(section, key).is_empty())
+            {
+                lints.push(lint(
+                    Kind::Shadowed,
+                    section,
+                    key,
+                    None,
+                    format!("never used, {} sets it too", shadowing.display()),
+                ));
+            }
+
+            let wildcard = format!("{}/*", mime.type_());
+            if *key != wildcard
+                && !values.is_empty()
+                && document.values(section, &wildcard) == values
+            {
+                // Handlers are looked up by the exact mime in every file
+                // before the wildcard, so the entry can only go if no other
+                // file sets the mime and the user's wildcard takes effect
+                let setting = |layers: &[(&Path, &Document)], key: &str| {
+                    layers
+                        .iter()
+                        .find(|(_, doc)| !doc.values(section, key).is_empty())
+                        .map(|(path, _)| path.display().to_string())
+                };
+                let taken_over = setting(higher, key)
+                    .or_else(|| setting(lower, key))
+                    .map(|path| (path, *key))
+                    .or_else(|| setting(higher, &wildcard).map(|path| (path, wildcard.as_str())));
+
+                let mut redundant = lint(Kind::Redundant, section, key, None, match &taken_over {
+                    None => format!("same handlers as {wildcard}"),
+                    Some((path, key)) => {
+                        format!("same handlers as {wildcard}, but {path} sets {key}")
+                    },
+                });
+                redundant.fixable = taken_over.is_none();
+                lints.push(redundant);
+            }
+        }
+
+        // Removing an app that is not installed is harmless
+        if Section::from_name(section) == Some(Section::RemovedAssociations) {
+            continue;
+        }
+
+        for name in values.iter().unique() {
+            let Ok(entry) = Handler::resolve(name.into()).and_then(|h| h.get_entry()) else {
+                lints.push(lint(
+                    Kind::DanglingHandler,
+                    section,
+                    key,
+                    Some(name),
+                    format!("{name} is not installed or has a broken desktop entry"),
+                ));
+                continue;
+            };
+
+            // Terminals never declare handlr's own terminal pseudo-scheme
+            let declared = *key == "x-scheme-handler/terminal"
+                || entry.mimes.iter().any(|m| {
+                    m == &mime || (mime.subtype() == mime::STAR && m.type_() == mime.type_())
+                });
+
+            if !declared {
+                lints.push(lint(
+                    Kind::UndeclaredMime,
+                    section,
+                    key,
+                    Some(name),
+                    format!("{name} does not declare {key} in its MimeType"),
+                ));
+            }
+        }
+    }
+
+    lints
+}
+
+/// The `(section, key, values)` that repair every fixable lint
+pub(crate) fn fixes(document: &Document, lints: &[Lint]) -> Vec<(String, String, Vec<String>)> {
+    use itertools::Itertools;
+
+    lints
+        .iter()
+        .filter(|l| l.fixable)
+        .into_group_map_by(|l| (l.section.clone(), l.mime.clone()))
+        .into_iter()
+        .sorted_by(|a, b| a.0.cmp(&b.0))
+        .map(|((section, key), lints)| {
+            let values = if lints.iter().any(|l| l.kind == Kind::Redundant) {
+                vec![]
+            } else {
+                document
+                    .values(&section, &key)
+                    .into_iter()
+                    .unique()
+                    .filter(|v| {
+                        !lints.iter().any(|l| {
+                            l.kind == Kind::DanglingHandler && l.handler.as_ref() == Some(v)
+                        })
+                    })
+                    .collect()
+            };
+
+            (section, key, values)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lint_and_fix() {
+        let document = Document::parse(
+            "[Default Applications]\ntext/*=handlr-lint-a.desktop;\ntext/plain=handlr-lint-a.\
+             desktop;\nimage=handlr-lint-b.desktop;\nimage/png=handlr-lint-c.desktop;\nimage/\
+             png=handlr-lint-c.desktop;handlr-lint-c.desktop;\n",
+        );
+        let higher = Document::parse("[Default Applications]\ntext/*=handlr-lint-d.desktop;\n");
+        let path = Path::new("mimeapps.list");
+
+        let lints = check(
+            path,
+            &document,
+            &[(Path::new("sway-mimeapps.list"), &higher)],
+            &[],
+        );
+        let kinds = lints
+            .iter()
+            .map(|l| (l.kind, l.mime.as_str(), l.fixable))
+            .collect::<Vec<_>>();
+
+        // text/plain is needed, as the shadowed text/* would not be used
+        assert_eq!(kinds, vec![
+            (Kind::DuplicateKey, "image/png", true),
+            (Kind::MalformedMime, "image", false),
+            (Kind::DuplicateHandler, "image/png", true),
+            (Kind::DanglingHandler, "image/png", true),
+            (Kind::Shadowed, "text/*", false),
+            (Kind::DanglingHandler, "text/*", true),
+            (Kind::Redundant, "text/plain", false),
+            (Kind::DanglingHandler, "text/plain", true),
+        ]);
+
+        // text/plain only goes because its handler is not installed
+        let fixes = fixes(&document, &lints);
+        assert_eq!(fixes, vec![
+            ("Default Applications".into(), "image/png".into(), vec![]),
+            ("Default Applications".into(), "text/*".into(), vec![]),
+            ("Default Applications".into(), "text/plain".into(), vec![]),
+        ]);
+    }
+
+    #[test]
+    fn redundant_only_fixable_if_handler_stays() {
+        let document =
+            Document::parse("[Default Applications]\ntext/*=a.desktop;\ntext/plain=a.desktop;\n");
+        let wildcard = Document::parse("[Default Applications]\ntext/*=b.desktop;\n");
+        let exact = Document::parse("[Default Applications]\ntext/plain=b.desktop;\n");
+
+        let fixable = |higher: &[(&Path, &Document)], lower: &[(&Path, &Document)]| {
+            check(Path::new("mimeapps.list"), &document, higher, lower)
+                .into_iter()
+                .find(|l| l.kind == Kind::Redundant)
+                .map(|l| l.fixable)
+        };
+        let other = Path::new("other-mimeapps.list");
+
+        assert_eq!(fixable(&[], &[]), Some(true));
+        assert_eq!(fixable(&[], &[(other, &wildcard)]), Some(true));
+        assert_eq!(fixable(&[(other, &wildcard)], &[]), Some(false));
+        assert_eq!(fixable(&[], &[(other, &exact)]), Some(false));
+        assert_eq!(fixable(&[(other, &exact)], &[]), Some(false));
+    }
+}
@@ -1,5 +1,13 @@
+mod document;
+mod history;
+mod lint;
+mod profile;
 mod system;
 mod user;
 
+pub(crate) use document::Document;
+pub(crate) use history::History;
+pub(crate) use lint::Lint;
+pub(crate) use profile::{Format as ProfileFormat, Profile};
 pub(crate) use system::SystemApps;
-pub(crate) use user::{MimeApps, Rule as MimeappsRule, APPS};
+pub(crate) use user::{MimeApps, Section, APPS};
@@ -0,0 +1,126 @@
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt::Write, path::Path};
+
+/// Formats a profile can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ArgEnum)]
+pub(crate) enum Format {
+    Toml,
+    Json,
+    /// A home-manager `xdg.mimeApps` snippet, which can only be exported
+    Nix,
+}
+
+impl Format {
+    /// Guess the format of a profile from its file extension, defaulting to
+    /// TOML
+    pub(crate) fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => Self::Json,
+            Some("nix") => Self::Nix,
+            _ => Self::Toml,
+        }
+    }
+}
+
+/// A portable set of associations that can be moved between machines
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Profile {
+    #[serde(default)]
+    pub(crate) default_applications: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub(crate) added_associations:   BTreeMap<String, Vec<String>>,
+}
+
+impl Profile {
+    pub(crate) fn read(path: &Path, format: Format) -> Result<Self> {
+        let raw = std::fs::read_to_string(path)?;
+
+        Ok(match format {
+            Format::Toml => toml::from_str(&raw)?,
+            Format::Json => serde_json::from_str(&raw)?,
+            Format::Nix => return Err(crate::Error::Unsupported("importing nix profiles")),
+        })
+    }
+
+    pub(crate) fn serialize(&self, format: Format) -> Result<String> {
+        Ok(match format {
+            Format::Toml => toml::to_string(self)?,
+            Format::Json => serde_json::to_string_pretty(self)?,
+            Format::Nix => self.to_nix(),
+        })
+    }
+
+    fn to_nix(&self) -> String {
+        let quote = |s: &str| {
+            format!(
+                "\"{}\"",
+                s.replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace("${", "\\${")
+            )
+        };
+        let attrs = |out: &mut String, map: &BTreeMap<String, Vec<String>>| {
+            for (mime, handlers) in map {
+                let handlers = handlers
+                    .iter()
+                    .map(|h| quote(h))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                let _ = writeln!(out, "      {} = [ {} ];", quote(mime), handlers);
+            }
+        };
+
+        let mut out = String::from("{\n  xdg.mimeApps = {\n    enable = true;\n");
+
+        out.push_str("    defaultApplications = {\n");
+        attrs(&mut out, &self.default_applications);
+        out.push_str("    };\n");
+
+        if !self.added_associations.is_empty() {
+            out.push_str("    associations.added = {\n");
+            attrs(&mut out, &self.added_associations);
+            out.push_str("    };\n");
+        }
+
+        out.push_str("  };\n}\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let mut profile = Profile::default();
+        profile
+            .default_applications
+            .insert("text/plain".into(), vec![
+                "nvim.desktop".into(),
+                "gedit.desktop".into(),
+            ]);
+        profile
+            .added_associations
+            .insert("image/png".into(), vec!["feh.desktop".into()]);
+
+        assert_eq!(
+            toml::from_str::<Profile>(&profile.serialize(Format::Toml)?)?,
+            profile
+        );
+        assert_eq!(
+            serde_json::from_str::<Profile>(&profile.serialize(Format::Json)?)?,
+            profile
+        );
+        assert_eq!(
+            profile.serialize(Format::Nix)?,
+            "{\n  xdg.mimeApps = {\n    enable = true;\n    defaultApplications = {\n      \
+             \"text/plain\" = [ \"nvim.desktop\" \"gedit.desktop\" ];\n    };\n    \
+             associations.added = {\n      \"image/png\" = [ \"feh.desktop\" ];\n    };\n  };\n}\n"
+        );
+
+        Ok(())
+    }
+}
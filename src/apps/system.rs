@@ -1,12 +1,8 @@
-use crate::{
-    common::{DesktopEntry, Handler},
-    Result,
-};
+use crate::common::{DesktopEntry, EntryIndex, Handler, ENTRIES};
 use mime::Mime;
 use std::{
     collections::{HashMap, VecDeque},
-    convert::TryFrom,
-    ffi::{OsStr, OsString},
+    ffi::OsString,
 };
 
 #[derive(Debug, Default, Clone)]
@@ -17,35 +13,93 @@ impl SystemApps {
         Some(self.0.get(mime)?.clone())
     }
 
-    pub(crate) fn get_handler(&self, mime: &Mime) -> Option<Handler> {
-        Some(self.get_handlers(mime)?.get(0).unwrap().clone())
+    pub(crate) fn get_entries() -> impl Iterator<Item = (OsString, DesktopEntry)> {
+        ENTRIES.entries().map(|e| (e.file_name.clone(), e.clone()))
     }
 
-    pub(crate) fn get_entries() -> Result<impl Iterator<Item = (OsString, DesktopEntry)>> {
-        Ok(xdg::BaseDirectories::new()?
-            .list_data_files_once("applications")
-            .into_iter()
-            .filter(|p| p.extension().and_then(OsStr::to_str) == Some("desktop"))
-            .filter_map(|p| {
-                Some((
-                    p.file_name().unwrap().to_owned(),
-                    DesktopEntry::try_from(p.clone()).ok()?,
-                ))
-            }))
+    /// Gather the mimes every installed app declares. Unless `include_hidden`
+    /// is set, apps that are hidden, missing their `TryExec` binary or not
+    /// shown in the current desktop are left out.
+    pub(crate) fn populate(include_hidden: bool) -> Self {
+        Self::scan(&ENTRIES, include_hidden, &crate::utils::current_desktops())
     }
 
-    pub(crate) fn populate() -> Result<Self> {
+    /// Build the `mime -> desktop file` map from the already parsed desktop
+    /// files of the index
+    fn scan(entries: &EntryIndex, include_hidden: bool, desktops: &[String]) -> Self {
         let mut map = HashMap::<Mime, VecDeque<Handler>>::with_capacity(50);
 
-        Self::get_entries()?.for_each(|(_, entry)| {
-            let (file_name, mimes) = (entry.file_name, entry.mimes);
-            for mime in mimes {
-                map.entry(mime)
+        // Desktop files hidden by one with the same ID in a directory of
+        // higher precedence are already skipped, even if that one is unusable
+        for entry in entries
+            .entries()
+            .filter(|e| include_hidden || (e.is_usable() && e.is_shown_in(desktops)))
+        {
+            for mime in &entry.mimes {
+                map.entry(mime.clone())
                     .or_default()
-                    .push_back(Handler::assume_valid(file_name.clone()));
+                    .push_back(Handler::assume_valid(entry.file_name.clone()));
             }
-        });
+        }
+
+        Self(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Result;
+    use std::str::FromStr;
+
+    #[test]
+    fn scan_index() -> Result<()> {
+        let data = tempfile::tempdir()?;
+        let (high, low) = (
+            data.path().join("high/applications"),
+            data.path().join("low/applications"),
+        );
+        std::fs::create_dir_all(&high)?;
+        std::fs::create_dir_all(&low)?;
+        let entry = |mimes: &str, extra: &str| {
+            format!("[Desktop Entry]\nName=app\nExec=app %U\nMimeType={mimes}\n{extra}")
+        };
+        std::fs::write(high.join("mpv.desktop"), entry("video/webm;", ""))?;
+        std::fs::write(low.join("mpv.desktop"), entry("video/mp4;", ""))?;
+        std::fs::write(high.join("vlc.desktop"), entry("video/mp4;", "Hidden=true"))?;
+        std::fs::write(low.join("vlc.desktop"), entry("video/mp4;", ""))?;
+        std::fs::write(
+            low.join("totem.desktop"),
+            entry("video/mp4;", "OnlyShowIn=GNOME;"),
+        )?;
+
+        let mut index = EntryIndex::default();
+        index.refresh(vec![high, low]);
+        let handlers = |apps: &SystemApps, mime: &str| -> Result<Vec<String>> {
+            let mut handlers = apps
+                .get_handlers(&Mime::from_str(mime)?)
+                .unwrap_or_default()
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            handlers.sort();
+            Ok(handlers)
+        };
+
+        // A hidden desktop file still hides the ones with its ID further down
+        let apps = SystemApps::scan(&index, false, &["sway".into()]);
+        assert_eq!(handlers(&apps, "video/webm")?, vec!["mpv.desktop"]);
+        assert!(handlers(&apps, "video/mp4")?.is_empty());
+
+        let apps = SystemApps::scan(&index, false, &["GNOME".into()]);
+        assert_eq!(handlers(&apps, "video/mp4")?, vec!["totem.desktop"]);
+
+        let apps = SystemApps::scan(&index, true, &[]);
+        assert_eq!(handlers(&apps, "video/mp4")?, vec![
+            "totem.desktop",
+            "vlc.desktop"
+        ]);
 
-        Ok(Self(map))
+        Ok(())
     }
 }
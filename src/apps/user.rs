@@ -1,17 +1,19 @@
 use crate::{
-    apps::SystemApps,
-    common::{DesktopEntry, Handler},
+    apps::{
+        history::{self, Change, KeyChange},
+        lint, Document, History, Lint, Profile, SystemApps,
+    },
+    common::{DesktopEntry, EntryIndex, Handler},
     utils, Error, Result, CONFIG,
 };
 use colored::Colorize;
 use mime::Mime;
-use once_cell::sync::Lazy;
-use pest::Parser;
+use once_cell::sync::{Lazy, OnceCell};
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     ffi::OsString,
     io::Read,
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::Command,
     str::FromStr,
 };
@@ -27,48 +29,331 @@ pub(crate) static CAT_PROG: Lazy<String> = Lazy::new(|| {
     }
 });
 
-#[derive(Debug, Default, Clone, pest_derive::Parser)]
-#[grammar = "common/ini.pest"]
+/// The sections of a `mimeapps.list` that handlr understands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Section {
+    AddedAssociations,
+    RemovedAssociations,
+    DefaultApplications,
+}
+
+impl Section {
+    const ALL: [Self; 3] = [
+        Self::AddedAssociations,
+        Self::RemovedAssociations,
+        Self::DefaultApplications,
+    ];
+
+    /// The section with the given header name
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|section| section.name() == name)
+    }
+
+    pub(crate) const fn name(self) -> &'static str {
+        match self {
+            Self::AddedAssociations => "Added Associations",
+            Self::RemovedAssociations => "Removed Associations",
+            Self::DefaultApplications => "Default Applications",
+        }
+    }
+}
+
+/// The associations parsed from a single `mimeapps.list` file, along with the
+/// document they were parsed from
+#[derive(Debug, Default, Clone)]
+pub(crate) struct MimeAppsFile {
+    pub(crate) path:      PathBuf,
+    document:             Document,
+    added_associations:   HashMap<Mime, VecDeque<Handler>>,
+    removed_associations: HashMap<Mime, VecDeque<Handler>>,
+    default_apps:         HashMap<Mime, VecDeque<Handler>>,
+}
+
+impl MimeAppsFile {
+    fn parse(path: PathBuf, raw_conf: &str) -> Self {
+        let document = Document::parse(raw_conf);
+        let mut conf = Self {
+            path,
+            ..Self::default()
+        };
+
+        for (section, name, value) in document.entries() {
+            let (Some(section), Ok(mime)) = (Section::from_name(section), Mime::from_str(name))
+            else {
+                continue;
+            };
+            // Removals of apps that are not installed still have to be kept
+            let removed = section == Section::RemovedAssociations;
+            let handlers = {
+                use itertools::Itertools;
+
+                value
+                    .split(';')
+                    .filter(|s| !s.is_empty())
+                    .unique()
+                    .filter_map(|s| {
+                        if removed {
+                            Some(Handler::assume_valid(s.into()))
+                        } else {
+                            Handler::from_str(s).ok()
+                        }
+                    })
+                    .collect::<VecDeque<_>>()
+            };
+
+            if !handlers.is_empty() {
+                conf.section_mut(section).insert(mime, handlers);
+            }
+        }
+
+        conf.document = document;
+        conf
+    }
+
+    /// Write raw `(section, key, values)` straight into the document, then
+    /// derive the associations from it again
+    fn set_raw<'a>(&mut self, entries: impl IntoIterator<Item = (&'a str, &'a str, &'a [String])>) {
+        for (section, key, values) in entries {
+            self.document.set(section, key, values);
+        }
+
+        *self = Self::parse(self.path.clone(), &self.document.to_string());
+    }
+
+    fn section_mut(&mut self, section: Section) -> &mut HashMap<Mime, VecDeque<Handler>> {
+        match section {
+            Section::AddedAssociations => &mut self.added_associations,
+            Section::RemovedAssociations => &mut self.removed_associations,
+            Section::DefaultApplications => &mut self.default_apps,
+        }
+    }
+
+    /// Append `handler` to the handlers of `mime`, keeping any handlers in the
+    /// document that could not be resolved
+    fn push(&mut self, section: Section, mime: Mime, handler: Handler) {
+        let mut values = self.document.values(section.name(), mime.essence_str());
+        if !values.contains(&handler.to_string()) {
+            values.push(handler.to_string());
+        }
+        self.document
+            .set(section.name(), mime.essence_str(), &values);
+
+        let handlers = self.section_mut(section).entry(mime).or_default();
+        if !handlers.contains(&handler) {
+            handlers.push_back(handler);
+        }
+    }
+
+    /// Replace the handlers of `mime`
+    fn replace(&mut self, section: Section, mime: Mime, handlers: VecDeque<Handler>) {
+        let values = handlers.iter().map(ToString::to_string).collect::<Vec<_>>();
+        self.document
+            .set(section.name(), mime.essence_str(), &values);
+        self.section_mut(section).insert(mime, handlers);
+    }
+
+    /// Remove every handler of `mime`
+    fn remove(&mut self, section: Section, mime: &Mime) -> Option<VecDeque<Handler>> {
+        self.document.set(section.name(), mime.essence_str(), &[]);
+        self.section_mut(section).remove(mime)
+    }
+
+    /// Remove `handler` from the handlers of `mime`
+    fn remove_one(&mut self, section: Section, mime: &Mime, handler: &Handler) {
+        let mut values = self.document.values(section.name(), mime.essence_str());
+        values.retain(|v| v != &handler.to_string());
+        self.document
+            .set(section.name(), mime.essence_str(), &values);
+
+        let handlers = self.section_mut(section);
+        if let Some(h) = handlers.get_mut(mime) {
+            h.retain(|h| h != handler);
+            if h.is_empty() {
+                handlers.remove(mime);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub(crate) struct MimeApps {
-    added_associations: HashMap<Mime, VecDeque<Handler>>,
-    default_apps:       HashMap<Mime, VecDeque<Handler>>,
-    system_apps:        SystemApps,
+    /// Every `mimeapps.list` on the search path, one layer per file, in order
+    /// of precedence
+    layers:      Vec<MimeAppsFile>,
+    /// Index of `$XDG_CONFIG_HOME/mimeapps.list` within `layers`, the only
+    /// layer that is ever written
+    user:        usize,
+    /// The user's `mimeapps.list` as it was read, to journal what a save
+    /// changed
+    saved:       Document,
+    /// Gathered from every installed desktop file the first time a lookup
+    /// falls through to it
+    system_apps: OnceCell<SystemApps>,
+}
+
+impl Default for MimeApps {
+    fn default() -> Self {
+        Self {
+            layers:      vec![MimeAppsFile::default()],
+            user:        0,
+            saved:       Document::default(),
+            system_apps: OnceCell::new(),
+        }
+    }
 }
 
 impl MimeApps {
+    fn user(&self) -> &MimeAppsFile {
+        &self.layers[self.user]
+    }
+
+    fn user_mut(&mut self) -> &mut MimeAppsFile {
+        &mut self.layers[self.user]
+    }
+
     pub(crate) fn add_handler(&mut self, mime: Mime, handler: Handler) {
-        self.default_apps
-            .entry(mime)
-            .or_default()
-            .push_back(handler);
+        self.user_mut()
+            .push(Section::DefaultApplications, mime, handler);
     }
 
     pub(crate) fn set_handler(&mut self, mime: Mime, handler: Handler) {
-        self.default_apps.insert(mime, vec![handler].into());
+        self.user_mut()
+            .replace(Section::DefaultApplications, mime, vec![handler].into());
     }
 
     pub(crate) fn remove_handler(&mut self, mime: &Mime) -> Result<()> {
-        if let Some(_removed) = self.default_apps.remove(mime) {
+        if let Some(_removed) = self.user_mut().remove(Section::DefaultApplications, mime) {
             self.save()?;
         }
 
         Ok(())
     }
 
+    /// Stop `handler` from ever being picked for `mime` through added
+    /// associations or the system apps
+    pub(crate) fn remove_association(&mut self, mime: Mime, handler: Handler) {
+        let user = self.user_mut();
+
+        user.remove_one(Section::AddedAssociations, &mime, &handler);
+        user.remove_one(Section::DefaultApplications, &mime, &handler);
+        user.push(Section::RemovedAssociations, mime, handler);
+    }
+
+    /// The effective default applications and added associations as a
+    /// portable profile
+    pub(crate) fn export(&self) -> Profile {
+        let to_map = |merged: HashMap<&Mime, (&VecDeque<Handler>, &Path)>| {
+            merged
+                .into_iter()
+                .map(|(mime, (handlers, _))| {
+                    (
+                        mime.to_string(),
+                        handlers.iter().map(ToString::to_string).collect(),
+                    )
+                })
+                .collect()
+        };
+
+        Profile {
+            default_applications: to_map(self.merged(|f| &f.default_apps)),
+            added_associations:   to_map(self.merged(|f| &f.added_associations)),
+        }
+    }
+
+    /// Apply a profile to the user's `mimeapps.list`, skipping malformed mimes
+    /// and handlers that are not installed. Returns a description of every
+    /// skipped entry.
+    pub(crate) fn import(&mut self, profile: Profile) -> Vec<String> {
+        let mut skipped = vec![];
+
+        for (section, entries) in [
+            (Section::DefaultApplications, profile.default_applications),
+            (Section::AddedAssociations, profile.added_associations),
+        ] {
+            for (mime, handlers) in entries {
+                let Ok(mime) = Mime::from_str(&mime) else {
+                    skipped.push(format!("{mime}: malformed mime"));
+                    continue;
+                };
+
+                let handlers = handlers
+                    .into_iter()
+                    .filter_map(|h| match Handler::resolve(h.clone().into()) {
+                        Ok(handler) => Some(handler),
+                        Err(_) => {
+                            skipped.push(format!("{mime}: {h} is not installed"));
+                            None
+                        },
+                    })
+                    .collect::<VecDeque<_>>();
+
+                if !handlers.is_empty() {
+                    self.user_mut().replace(section, mime, handlers);
+                }
+            }
+        }
+
+        skipped
+    }
+
+    /// Merge one section of every layer, keeping the highest precedence entry
+    /// for each mime along with the file it came from
+    fn merged(
+        &self,
+        section: fn(&MimeAppsFile) -> &HashMap<Mime, VecDeque<Handler>>,
+    ) -> HashMap<&Mime, (&VecDeque<Handler>, &Path)> {
+        let mut merged = HashMap::new();
+
+        for file in &self.layers {
+            for (mime, handlers) in section(file) {
+                merged
+                    .entry(mime)
+                    .or_insert((handlers, file.path.as_path()));
+            }
+        }
+
+        merged
+    }
+
+    /// Show every desktop file with the handler's ID like `systemctl cat`,
+    /// marking the one that is used and the ones it shadows. With `effective`,
+    /// only show the entry handlr uses, as it understands it.
     #[allow(clippy::unused_self)]
-    pub(crate) fn cat_handler(&self, handler: &Handler) -> Result<()> {
-        let path = handler.get_entry()?.file_name;
-        let to_cat = xdg::BaseDirectories::new()?
-            .list_data_files_once("applications")
-            .into_iter()
-            .find(|f| f.file_name() == Some(&path));
-
-        if let Some(file) = to_cat {
+    pub(crate) fn cat_handler(&self, handler: &Handler, effective: bool) -> Result<()> {
+        use std::io::Write;
+
+        let files = EntryIndex::find_all(&handler.0).collect::<Vec<_>>();
+        let Some(first) = files.first() else {
+            println!("File does not exist");
+            return Ok(());
+        };
+
+        if effective {
+            let entry = EntryIndex::lookup(first).ok_or_else(|| Error::BadEntry(first.clone()))?;
+            println!("{}", format!("# {}", first.display()).bold());
+            print!("{}", entry.render());
+            return Ok(());
+        }
+
+        for (i, file) in files.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+            let state = if i == 0 {
+                "(effective)".green()
+            } else {
+                "(shadowed)".yellow()
+            };
+            println!("{} {state}", format!("# {}", file.display()).bold());
+            // The file is printed by another process
+            std::io::stdout().flush()?;
+
             Command::new(CAT_PROG.to_string())
                 .arg(file.display().to_string())
                 .status()?;
-        } else {
-            println!("File does not exist");
         }
 
         Ok(())
@@ -76,13 +361,7 @@ impl MimeApps {
 
     #[allow(clippy::unused_self)]
     pub(crate) fn edit_handler(&self, handler: &Handler) -> Result<()> {
-        let path = handler.get_entry()?.file_name;
-
-        if let Some(file) = xdg::BaseDirectories::new()?
-            .list_data_files_once("applications")
-            .into_iter()
-            .find(|f| f.file_name() == Some(&path))
-        {
+        if let Some(file) = Handler::get_path(&handler.0) {
             Command::new(DEFAULT_EDITOR.to_string())
                 .arg(file.display().to_string())
                 .status()?;
@@ -109,9 +388,8 @@ impl MimeApps {
 
         let table = ascii_table::AsciiTable::default();
 
-        for harr in self.default_apps.clone() {
-            let mime = harr.0;
-            for h in harr.1 {
+        for (mime, (handlers, _)) in self.merged(|f| &f.default_apps) {
+            for h in handlers {
                 if desktop == h.0 {
                     enabled.insert(
                         // Ord is not satisfied for `ColoredString`
@@ -136,23 +414,27 @@ impl MimeApps {
     }
 
     #[allow(clippy::unnecessary_wraps, clippy::unused_self)]
+    #[allow(clippy::fn_params_excessive_bools)]
     pub(crate) fn ask_handler(
         &self,
         mime: &Mime,
         skim: bool,
         _plain: bool, // TODO: use or delete
         config: bool,
+        include_hidden: bool,
     ) -> Result<DesktopEntry> {
         let globs = mime_db::extensions(mime.clone())
             .unwrap_or_else(|| mime_db::extensions(Mime::from_str("text/plain").unwrap()).unwrap())
             .collect::<Vec<_>>();
 
         let mut apps = vec![];
-        let get_apps = |m: &Mime| -> Result<Vec<DesktopEntry>> {
-            Ok(SystemApps::get_entries()?
+        let desktops = utils::current_desktops();
+        let get_apps = |m: &Mime| -> Vec<DesktopEntry> {
+            SystemApps::get_entries()
+                .filter(|(_, entry)| include_hidden || entry.should_show(&desktops))
                 .filter(|(_, entry)| entry.mimes.iter().any(|mime| mime == m))
                 .map(|i| i.1)
-                .collect::<Vec<_>>())
+                .collect::<Vec<_>>()
         };
 
         if let Ok(handler) = self.get_handler(mime) {
@@ -161,12 +443,12 @@ impl MimeApps {
             }
         }
 
-        let mut system = get_apps(mime)?;
+        let mut system = get_apps(mime);
 
         if system.len() < 10 {
             for glob in &globs {
                 if let Some(gmime) = mime_db::lookup(glob) {
-                    for app in get_apps(&(Mime::from_str(gmime)?))? {
+                    for app in get_apps(&(Mime::from_str(gmime)?)) {
                         system.push(app);
                     }
                 }
@@ -178,18 +460,18 @@ impl MimeApps {
 
         apps.append(&mut system);
         apps.dedup();
+        let apps = apps
+            .iter()
+            .flat_map(DesktopEntry::with_actions)
+            .collect::<Vec<_>>();
 
         let selected = if skim {
             utils::skim::skim_select_item(&apps)
         } else if config {
             // Remove unnecessary double iter
-            let name = CONFIG.select(
-                apps.iter()
-                    .map(|app| app.file_name.to_str().unwrap().replace("\"", "")),
-            )?;
+            let name = CONFIG.select(apps.iter().map(DesktopEntry::selector_id))?;
 
-            apps.iter()
-                .find(|app| app.file_name.to_str().unwrap().replace("\"", "") == name)
+            apps.iter().find(|app| app.selector_id() == name)
         } else if let Some(idx) = utils::select_item(
             "Select handler:",
             &apps
@@ -222,10 +504,21 @@ impl MimeApps {
     }
 
     fn get_handler_from_user(&self, mime: &Mime) -> Result<Handler> {
-        match self.default_apps.get(mime) {
+        // A layer whose handlers are all unusable falls through to the next
+        let handlers = self.layers.iter().find_map(|f| {
+            let handlers = f
+                .default_apps
+                .get(mime)?
+                .iter()
+                .filter(|h| h.is_usable())
+                .collect::<Vec<_>>();
+            (!handlers.is_empty()).then_some(handlers)
+        });
+
+        match handlers {
             Some(handlers) if CONFIG.enable_selector && handlers.len() > 1 => {
                 let handlers = handlers
-                    .iter()
+                    .into_iter()
                     .map(|h| (h, h.get_entry().unwrap().name))
                     .collect::<Vec<_>>();
 
@@ -242,16 +535,31 @@ impl MimeApps {
 
                 Ok(handler)
             },
-            Some(handlers) => Ok(handlers.get(0).unwrap().clone()),
-            None => Err(Error::NotFound(mime.to_string())),
+            Some(handlers) if !handlers.is_empty() => Ok(handlers[0].clone()),
+            _ => Err(Error::NotFound(mime.to_string())),
         }
     }
 
+    /// Walk the added associations of each layer, skipping handlers that a
+    /// higher precedence layer removed, before falling back to the system apps
     fn get_handler_from_added_associations(&self, mime: &Mime) -> Result<Handler> {
-        self.added_associations
-            .get(mime)
-            .map(|h| h.get(0).unwrap().clone())
-            .or_else(|| self.system_apps.get_handler(mime))
+        let mut removed = HashSet::new();
+
+        for layer in &self.layers {
+            if let Some(handler) = layer.added_associations.get(mime).and_then(|handlers| {
+                handlers
+                    .iter()
+                    .find(|h| !removed.contains(h) && h.is_usable())
+            }) {
+                return Ok(handler.clone());
+            }
+
+            removed.extend(layer.removed_associations.get(mime).into_iter().flatten());
+        }
+
+        self.system_apps()
+            .get_handlers(mime)
+            .and_then(|handlers| handlers.into_iter().find(|h| !removed.contains(h)))
             .ok_or_else(|| Error::NotFound(mime.to_string()))
     }
 
@@ -259,11 +567,13 @@ impl MimeApps {
         let handler = self.get_handler(mime)?;
         let output = if output_json {
             let entry = handler.get_entry()?;
-            let cmd = entry.get_cmd(vec![])?;
+            let cmd = entry.get_cmd(vec![], None, false)?;
 
             (json::object! {
                 handler: handler.to_string(),
                 name: entry.name.as_str(),
+                generic_name: entry.generic_name.as_deref(),
+                comment: entry.comment.as_deref(),
                 cmd: cmd.0 + " " + &cmd.1.join(" "),
             })
             .to_string()
@@ -280,101 +590,241 @@ impl MimeApps {
         Ok(config)
     }
 
+    /// Every `mimeapps.list` on the search path of the MIME apps spec, in
+    /// order of precedence
+    pub(crate) fn paths() -> Result<Vec<PathBuf>> {
+        use itertools::Itertools;
+
+        let dirs = xdg::BaseDirectories::new()?;
+        let names = utils::current_desktops()
+            .iter()
+            .map(|desktop| format!("{}-mimeapps.list", desktop.to_lowercase()))
+            .chain(std::iter::once("mimeapps.list".to_owned()))
+            .collect::<Vec<_>>();
+
+        Ok(std::iter::once(dirs.get_config_home())
+            .chain(dirs.get_config_dirs())
+            .chain(
+                std::iter::once(dirs.get_data_home())
+                    .chain(dirs.get_data_dirs())
+                    .map(|dir| dir.join("applications")),
+            )
+            .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+            .unique()
+            .collect())
+    }
+
     pub(crate) fn read() -> Result<Self> {
-        let raw_conf = {
-            let mut buf = String::new();
-            let exists = std::path::Path::new(&Self::path()?).exists();
-            std::fs::OpenOptions::new()
-                .write(!exists)
-                .create(!exists)
-                .read(true)
-                .open(Self::path()?)?
-                .read_to_string(&mut buf)?;
-            buf
-        };
-        let file = Self::parse(Rule::file, &raw_conf)?.next().unwrap();
+        Self::read_from(&Self::path()?, Self::paths()?)
+    }
 
-        let mut current_section_name = "".to_string();
-        let mut conf = Self {
-            added_associations: HashMap::default(),
-            default_apps:       HashMap::default(),
-            system_apps:        SystemApps::populate()?,
-        };
+    /// Read every layer in `paths`. Layers other than the user's are not
+    /// handlr's to fix, so ones that cannot be read are skipped with a warning.
+    fn read_from(user_path: &Path, paths: Vec<PathBuf>) -> Result<Self> {
+        let mut user = 0;
+        let mut layers = vec![];
 
-        file.into_inner().for_each(|line| {
-            match line.as_rule() {
-                Rule::section => {
-                    current_section_name = line.into_inner().concat();
-                },
-                Rule::property => {
-                    let mut inner_rules = line.into_inner(); // { name ~ "=" ~ value }
-
-                    let name = inner_rules.next().unwrap().as_str();
-                    let handlers = {
-                        use itertools::Itertools;
-
-                        inner_rules
-                            .next()
-                            .unwrap()
-                            .as_str()
-                            .split(';')
-                            .filter(|s| !s.is_empty())
-                            .unique()
-                            .filter_map(|s| Handler::from_str(s).ok())
-                            .collect::<VecDeque<_>>()
-                    };
-
-                    if !handlers.is_empty() {
-                        match (Mime::from_str(name), current_section_name.as_str()) {
-                            (Ok(mime), "Added Associations") =>
-                                conf.added_associations.insert(mime, handlers),
-
-                            (Ok(mime), "Default Applications") =>
-                                conf.default_apps.insert(mime, handlers),
-                            _ => None,
-                        };
-                    }
-                },
-                _ => {},
+        for path in paths {
+            if path == user_path {
+                let exists = path.exists();
+                let mut buf = String::new();
+                std::fs::OpenOptions::new()
+                    .write(!exists)
+                    .create(!exists)
+                    .read(true)
+                    .open(&path)?
+                    .read_to_string(&mut buf)?;
+
+                user = layers.len();
+                layers.push(MimeAppsFile::parse(path, &buf));
+            } else if path.is_file() {
+                match std::fs::read_to_string(&path) {
+                    Ok(raw_conf) => layers.push(MimeAppsFile::parse(path, &raw_conf)),
+                    Err(e) => eprintln!(
+                        "[{}]: skipping {}: {e}",
+                        "warning".yellow().bold(),
+                        path.display()
+                    ),
+                }
             }
-        });
+        }
 
-        Ok(conf)
+        Ok(Self {
+            saved: layers[user].document.clone(),
+            layers,
+            user,
+            system_apps: OnceCell::new(),
+        })
     }
 
-    pub(crate) fn save(&self) -> Result<()> {
-        use itertools::Itertools;
-        use std::io::{prelude::*, BufWriter};
+    /// Take an exclusive advisory lock that serializes read-modify-write
+    /// cycles of the user's `mimeapps.list`. It is held until the returned
+    /// file is dropped.
+    pub(crate) fn lock() -> Result<std::fs::File> {
+        use fs2::FileExt;
 
-        let f = std::fs::OpenOptions::new()
-            .read(true)
+        let dirs = xdg::BaseDirectories::with_prefix("handlr")?;
+        let path = if dirs.has_runtime_directory() {
+            dirs.place_runtime_file("mimeapps.list.lock")?
+        } else {
+            dirs.place_cache_file("mimeapps.list.lock")?
+        };
+
+        let file = std::fs::OpenOptions::new()
             .create(true)
             .write(true)
-            .truncate(true)
-            .open(Self::path()?)?;
-        let mut writer = BufWriter::new(f);
-
-        writer.write_all(b"[Added Associations]\n")?;
-        for (k, v) in self.added_associations.iter().sorted() {
-            writer.write_all(k.essence_str().as_ref())?;
-            writer.write_all(b"=")?;
-            writer.write_all(v.iter().join(";").as_ref())?;
-            writer.write_all(b";\n")?;
+            .truncate(false)
+            .open(path)?;
+        file.lock_exclusive()?;
+
+        Ok(file)
+    }
+
+    pub(crate) fn save(&self) -> Result<()> {
+        self.save_reverting(vec![])
+    }
+
+    /// Write the user's `mimeapps.list` and journal what changed, recording
+    /// the ids of the changes this write rolls back
+    fn save_reverting(&self, reverts: Vec<u64>) -> Result<()> {
+        let document = &self.user().document;
+        utils::write_atomic(&Self::path()?, document.to_string().as_bytes())?;
+        History::record(history::diff(&self.saved, document), reverts)
+    }
+
+    /// Roll back the last `count` changes that are still in effect
+    pub(crate) fn undo(&mut self, count: usize) -> Result<()> {
+        self.revert(&History::read()?.last_changes(count))
+    }
+
+    /// Roll back every change made after the change with the given id
+    pub(crate) fn revert_to(&mut self, id: u64) -> Result<()> {
+        self.revert(&History::read()?.changes_after(id)?)
+    }
+
+    /// Roll back the given changes and save the result
+    fn revert(&mut self, changes: &[Change]) -> Result<()> {
+        if changes.is_empty() {
+            println!("Nothing to undo");
+            return Ok(());
         }
 
-        writer.write_all(b"\n[Default Applications]\n")?;
-        for (k, v) in self.default_apps.iter().sorted() {
-            writer.write_all(k.essence_str().as_ref())?;
-            writer.write_all(b"=")?;
-            writer.write_all(v.iter().join(";").as_ref())?;
-            writer.write_all(b";\n")?;
+        self.roll_back(changes);
+        self.save_reverting(changes.iter().map(|c| c.id).collect())
+    }
+
+    /// Restore the values keys had before each change, newest change first
+    fn roll_back(&mut self, changes: &[Change]) {
+        self.user_mut().set_raw(changes.iter().flat_map(|change| {
+            change.changes.iter().rev().map(|key| {
+                (
+                    key.section.as_str(),
+                    key.mime.as_str(),
+                    key.before.as_slice(),
+                )
+            })
+        }));
+    }
+
+    /// The changes the user's `mimeapps.list` needs to match a desired state.
+    /// Mimes that are not part of the desired state are left alone.
+    pub(crate) fn plan(&self, desired: &Profile) -> Result<Vec<KeyChange>> {
+        let mut plan = vec![];
+
+        for (section, entries) in [
+            (Section::DefaultApplications, &desired.default_applications),
+            (Section::AddedAssociations, &desired.added_associations),
+        ] {
+            for (mime, handlers) in entries {
+                let mime = Mime::from_str(mime)?;
+                let before = self
+                    .user()
+                    .document
+                    .values(section.name(), mime.essence_str());
+
+                if &before != handlers {
+                    plan.push(KeyChange {
+                        section: section.name().to_owned(),
+                        mime: mime.essence_str().to_owned(),
+                        before,
+                        after: handlers.clone(),
+                    });
+                }
+            }
         }
 
-        writer.flush()?;
-        Ok(())
+        Ok(plan)
+    }
+
+    /// Print a plan along with a summary, like a terraform plan
+    pub(crate) fn print_plan(plan: &[KeyChange]) {
+        let count = |f: fn(&KeyChange) -> bool| plan.iter().filter(|c| f(c)).count();
+
+        for change in plan {
+            println!("{change}");
+        }
+
+        println!(
+            "\nPlan: {} to add, {} to change, {} to remove",
+            count(|c| c.before.is_empty()),
+            count(|c| !c.before.is_empty() && !c.after.is_empty()),
+            count(|c| c.after.is_empty()),
+        );
+    }
+
+    /// Apply every change of a plan at once. Nothing is changed unless every
+    /// handler in the plan is installed.
+    pub(crate) fn apply(&mut self, plan: &[KeyChange]) -> Result<()> {
+        for handler in plan.iter().flat_map(|key| &key.after) {
+            Handler::resolve(handler.into())?;
+        }
+
+        self.user_mut().set_raw(plan.iter().map(|key| {
+            (
+                key.section.as_str(),
+                key.mime.as_str(),
+                key.after.as_slice(),
+            )
+        }));
+
+        self.save()
+    }
+
+    /// Problems in the user's `mimeapps.list`
+    pub(crate) fn lint(&self) -> Vec<Lint> {
+        fn documents(layers: &[MimeAppsFile]) -> Vec<(&std::path::Path, &Document)> {
+            layers
+                .iter()
+                .map(|layer| (layer.path.as_path(), &layer.document))
+                .collect()
+        }
+
+        lint::check(
+            &self.user().path,
+            &self.user().document,
+            &documents(&self.layers[..self.user]),
+            &documents(&self.layers[self.user + 1..]),
+        )
+    }
+
+    /// Repair every fixable lint and save the result
+    pub(crate) fn fix(&mut self, lints: &[Lint]) -> Result<()> {
+        let fixes = lint::fixes(&self.user().document, lints);
+
+        self.user_mut().set_raw(
+            fixes
+                .iter()
+                .map(|(section, key, values)| (section.as_str(), key.as_str(), values.as_slice())),
+        );
+
+        self.save()
     }
 
-    pub(crate) fn print(&self, detailed: bool) {
+    fn system_apps(&self) -> &SystemApps {
+        self.system_apps.get_or_init(|| SystemApps::populate(false))
+    }
+
+    pub(crate) fn print(&self, detailed: bool, include_hidden: bool) {
         use itertools::Itertools;
 
         let to_rows = |map: &HashMap<Mime, VecDeque<Handler>>| {
@@ -384,19 +834,49 @@ impl MimeApps {
                 .collect::<Vec<_>>()
         };
 
+        let to_rows_with_source = |map: HashMap<&Mime, (&VecDeque<Handler>, &Path)>| {
+            map.into_iter()
+                .sorted()
+                .map(|(k, (v, source))| {
+                    vec![
+                        k.to_string(),
+                        v.iter().join(", "),
+                        source.display().to_string(),
+                    ]
+                })
+                .collect::<Vec<_>>()
+        };
+
         let table = ascii_table::AsciiTable::default();
 
         if detailed {
             println!("Default Apps");
-            table.print(to_rows(&self.default_apps));
-            if !self.added_associations.is_empty() {
+            table.print(to_rows_with_source(self.merged(|f| &f.default_apps)));
+            let added_associations = self.merged(|f| &f.added_associations);
+            if !added_associations.is_empty() {
                 println!("Added Associations");
-                table.print(to_rows(&self.added_associations));
+                table.print(to_rows_with_source(added_associations));
+            }
+            let removed_associations = self.merged(|f| &f.removed_associations);
+            if !removed_associations.is_empty() {
+                println!("Removed Associations");
+                table.print(to_rows_with_source(removed_associations));
             }
             println!("System Apps");
-            table.print(to_rows(&self.system_apps.0));
+            let system_apps = if include_hidden {
+                SystemApps::populate(true)
+            } else {
+                self.system_apps().clone()
+            };
+            table.print(to_rows(&system_apps.0));
         } else {
-            table.print(to_rows(&self.default_apps));
+            table.print(
+                self.merged(|f| &f.default_apps)
+                    .into_iter()
+                    .sorted()
+                    .map(|(k, (v, _))| vec![k.to_string(), v.iter().join(", ")])
+                    .collect::<Vec<_>>(),
+            );
         }
     }
 
@@ -406,12 +886,13 @@ impl MimeApps {
         let stdout = std::io::stdout();
         let mut stdout = stdout.lock();
 
-        SystemApps::get_entries()?.for_each(|(_, e)| {
-            stdout.write_all(e.file_name.as_bytes()).unwrap();
-            stdout.write_all(b"\t").unwrap();
-            stdout.write_all(e.name.as_bytes()).unwrap();
-            stdout.write_all(b"\n").unwrap();
-        });
+        let desktops = utils::current_desktops();
+        for (_, e) in SystemApps::get_entries().filter(|(_, e)| e.should_show(&desktops)) {
+            stdout.write_all(e.file_name.as_bytes())?;
+            stdout.write_all(b"\t")?;
+            stdout.write_all(e.name.as_bytes())?;
+            stdout.write_all(b"\n")?;
+        }
 
         Ok(())
     }
@@ -452,6 +933,268 @@ mod tests {
                 .to_string(),
             "brave.desktop"
         );
+        // Nothing fell through to the installed apps, so they were never scanned
+        assert!(user_apps.system_apps.get().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn layer_precedence() -> Result<()> {
+        let layer = |path: &str, mime: &str, handler: &str| -> Result<MimeAppsFile> {
+            let mut file = MimeAppsFile {
+                path: PathBuf::from(path),
+                ..MimeAppsFile::default()
+            };
+            file.default_apps.insert(
+                Mime::from_str(mime)?,
+                vec![Handler::assume_valid(handler.into())].into(),
+            );
+            Ok(file)
+        };
+
+        let mut user_apps = MimeApps {
+            layers: vec![
+                layer("sway-mimeapps.list", "text/plain", "gedit.desktop")?,
+                layer("mimeapps.list", "text/plain", "nvim.desktop")?,
+                layer("/etc/xdg/mimeapps.list", "text/html", "firefox.desktop")?,
+            ],
+            user: 1,
+            ..MimeApps::default()
+        };
+
+        assert_eq!(
+            user_apps
+                .get_handler(&Mime::from_str("text/plain")?)?
+                .to_string(),
+            "gedit.desktop"
+        );
+        assert_eq!(
+            user_apps
+                .get_handler(&Mime::from_str("text/html")?)?
+                .to_string(),
+            "firefox.desktop"
+        );
+
+        user_apps.set_handler(
+            Mime::from_str("text/html")?,
+            Handler::assume_valid("brave.desktop".into()),
+        );
+        assert_eq!(
+            user_apps
+                .get_handler(&Mime::from_str("text/html")?)?
+                .to_string(),
+            "brave.desktop"
+        );
+        assert!(user_apps.layers[2]
+            .default_apps
+            .contains_key(&Mime::from_str("text/html")?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn unusable_handlers_fall_through() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let entry = |name: &str, extra: &str| -> Result<String> {
+            let path = dir.path().join(name);
+            std::fs::write(
+                &path,
+                format!("[Desktop Entry]\nName={name}\nExec=true %f\n{extra}"),
+            )?;
+            // An absolute path is found whatever the data dirs are
+            Ok(path.display().to_string())
+        };
+        let (hidden, ed) = (
+            entry("hid.desktop", "Hidden=true\n")?,
+            entry("ed.desktop", "")?,
+        );
+
+        let user_apps = MimeApps {
+            layers: vec![
+                MimeAppsFile::parse(
+                    "mimeapps.list".into(),
+                    &format!("[Default Applications]\ntext/html={hidden};\n"),
+                ),
+                MimeAppsFile::parse(
+                    "/etc/xdg/mimeapps.list".into(),
+                    &format!("[Default Applications]\ntext/html={ed};\n"),
+                ),
+            ],
+            ..MimeApps::default()
+        };
+
+        assert_eq!(
+            user_apps
+                .get_handler(&Mime::from_str("text/html")?)?
+                .to_string(),
+            ed
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_layers() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name);
+        std::fs::write(
+            path("kde-mimeapps.list"),
+            "[Default Applications]\ntext/plain=\n",
+        )?;
+        std::fs::write(path("gnome-mimeapps.list"), [0xff, 0xfe])?;
+        std::fs::write(
+            path("system-mimeapps.list"),
+            "[Default Applications]\ntext/html=firefox.desktop;\n",
+        )?;
+
+        let user_apps = MimeApps::read_from(&path("mimeapps.list"), vec![
+            path("kde-mimeapps.list"),
+            path("gnome-mimeapps.list"),
+            path("mimeapps.list"),
+            path("system-mimeapps.list"),
+        ])?;
+
+        assert_eq!(
+            user_apps
+                .layers
+                .iter()
+                .map(|l| l.path.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                path("kde-mimeapps.list"),
+                path("mimeapps.list"),
+                path("system-mimeapps.list")
+            ]
+        );
+        assert_eq!(user_apps.user().path, path("mimeapps.list"));
+        assert_eq!(
+            user_apps.layers[2]
+                .document
+                .values("Default Applications", "text/html"),
+            vec!["firefox.desktop"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn removed_associations() -> Result<()> {
+        let mime = Mime::from_str("image/png")?;
+        let mut system = MimeAppsFile {
+            path: PathBuf::from("/usr/share/applications/mimeapps.list"),
+            ..MimeAppsFile::default()
+        };
+        system.added_associations.insert(
+            mime.clone(),
+            vec![
+                Handler::assume_valid("gimp.desktop".into()),
+                Handler::assume_valid("feh.desktop".into()),
+            ]
+            .into(),
+        );
+
+        let mut user_apps = MimeApps {
+            layers: vec![MimeAppsFile::default(), system],
+            user: 0,
+            ..MimeApps::default()
+        };
+        user_apps.system_apps = OnceCell::from(SystemApps(
+            [(
+                mime.clone(),
+                vec![Handler::assume_valid("gimp.desktop".into())].into(),
+            )]
+            .into(),
+        ));
+
+        assert_eq!(user_apps.get_handler(&mime)?.to_string(), "gimp.desktop");
+
+        user_apps.remove_association(mime.clone(), Handler::assume_valid("gimp.desktop".into()));
+        assert_eq!(user_apps.get_handler(&mime)?.to_string(), "feh.desktop");
+
+        user_apps.remove_association(mime.clone(), Handler::assume_valid("feh.desktop".into()));
+        user_apps.get_handler(&mime).unwrap_err();
+
+        Ok(())
+    }
+
+    #[test]
+    fn revert_to_change() -> Result<()> {
+        let states = [
+            "",
+            "[Default Applications]\ntext/plain=nvim.desktop;\n",
+            "[Default Applications]\ntext/plain=gedit.desktop;\n",
+            "[Default Applications]\ntext/plain=gedit.desktop;\n\n[Added \
+             Associations]\nimage/png=feh.desktop;\n",
+        ];
+        let history = History(
+            states
+                .windows(2)
+                .zip(1..)
+                .map(|(states, id)| Change {
+                    id,
+                    timestamp: 0,
+                    command: String::new(),
+                    changes: history::diff(
+                        &Document::parse(states[0]),
+                        &Document::parse(states[1]),
+                    ),
+                    reverts: vec![],
+                })
+                .collect(),
+        );
+
+        let mut user_apps = MimeApps {
+            layers: vec![MimeAppsFile::parse("mimeapps.list".into(), states[3])],
+            ..MimeApps::default()
+        };
+        user_apps.roll_back(&history.changes_after(1)?);
+
+        let document = &user_apps.user().document;
+        assert_eq!(document.values("Default Applications", "text/plain"), vec![
+            "nvim.desktop"
+        ]);
+        assert!(document
+            .values("Added Associations", "image/png")
+            .is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn plan_desired_state() -> Result<()> {
+        let mut user_apps = MimeApps::default();
+        user_apps.set_handler(
+            Mime::from_str("text/plain")?,
+            Handler::assume_valid("nvim.desktop".into()),
+        );
+
+        let mut desired = Profile::default();
+        desired
+            .default_applications
+            .insert("text/plain".into(), vec!["nvim.desktop".into()]);
+        assert!(user_apps.plan(&desired)?.is_empty());
+
+        desired
+            .default_applications
+            .insert("text/plain".into(), vec!["gedit.desktop".into()]);
+        desired
+            .added_associations
+            .insert("image/png".into(), vec!["feh.desktop".into()]);
+        assert_eq!(user_apps.plan(&desired)?, vec![
+            KeyChange {
+                section: "Default Applications".into(),
+                mime:    "text/plain".into(),
+                before:  vec!["nvim.desktop".into()],
+                after:   vec!["gedit.desktop".into()],
+            },
+            KeyChange {
+                section: "Added Associations".into(),
+                mime:    "image/png".into(),
+                before:  vec![],
+                after:   vec!["feh.desktop".into()],
+            },
+        ]);
 
         Ok(())
     }
@@ -1,4 +1,8 @@
-use crate::common::{Handler, MimeOrExtension, UserPath};
+use crate::{
+    apps::ProfileFormat,
+    common::{Handler, MimeOrExtension, UserPath},
+};
+use std::path::PathBuf;
 
 #[derive(clap::Parser)]
 #[clap(
@@ -12,13 +16,26 @@ pub(crate) enum Cmd {
     #[clap(aliases = &["ls", "l", "list"])]
     List {
         #[clap(long, short)]
-        all: bool,
+        all:            bool,
+        /// Include apps that are hidden, not installed or meant for other
+        /// desktops in the system apps
+        #[clap(long)]
+        include_hidden: bool,
     },
 
     /// Open a path/URL with its default handler
     Open {
         #[clap(required = true)]
-        paths: Vec<UserPath>,
+        paths:   Vec<UserPath>,
+        /// Run one of the handler's desktop actions, like new-private-window
+        #[clap(long)]
+        action:  Option<String>,
+        /// Print the commands that would run instead of running them
+        #[clap(long)]
+        dry_run: bool,
+        /// Print the commands of --dry-run as json
+        #[clap(long, requires = "dry-run")]
+        json:    bool,
     },
 
     /// Set the default handler for mime/extension
@@ -32,8 +49,17 @@ pub(crate) enum Cmd {
 
     /// Launch the handler for specified extension/mime with optional arguments
     Launch {
-        mime: MimeOrExtension,
-        args: Vec<UserPath>,
+        mime:    MimeOrExtension,
+        args:    Vec<UserPath>,
+        /// Run one of the handler's desktop actions, like new-private-window
+        #[clap(long)]
+        action:  Option<String>,
+        /// Print the commands that would run instead of running them
+        #[clap(long)]
+        dry_run: bool,
+        /// Print the commands of --dry-run as json
+        #[clap(long, requires = "dry-run")]
+        json:    bool,
     },
 
     /// Get handler for this mime/extension
@@ -50,11 +76,105 @@ pub(crate) enum Cmd {
         handler: Handler,
     },
 
+    /// Remove a handler from the associations of a mime/extension, so it is
+    /// never picked for it
+    RemoveAssociation {
+        mime:    MimeOrExtension,
+        /// Desktop file ID, which does not need to be installed
+        handler: String,
+    },
+
+    /// Show the journal of changes made to mimeapps.list
+    History,
+
+    /// Undo the last changes made to mimeapps.list
+    Undo {
+        /// Number of changes to undo
+        #[clap(default_value = "1")]
+        count: usize,
+    },
+
+    /// Undo every change made to mimeapps.list after the one with the given id
+    RevertTo { id: u64 },
+
+    /// Print the effective default apps and added associations as a profile
+    Export {
+        #[clap(long, short, arg_enum, default_value = "toml")]
+        format: ProfileFormat,
+    },
+
+    /// Apply a profile created by `handlr export`
+    Import {
+        file:   PathBuf,
+        /// Format of the profile, guessed from the file extension by default
+        #[clap(long, short, arg_enum)]
+        format: Option<ProfileFormat>,
+    },
+
+    /// Make mimeapps.list match a desired state, like
+    /// ~/.config/handlr/associations.toml. Mimes that are not listed in it are
+    /// left alone.
+    Apply {
+        /// Desired state in the same format as `handlr export`
+        file:  Option<PathBuf>,
+        /// Only check for drift, exiting with an error if there is any
+        #[clap(long)]
+        check: bool,
+    },
+
+    /// Report problems in mimeapps.list, like handlers that are not installed
+    Lint {
+        /// Apply the repairs that do not change which handler is picked
+        #[clap(long)]
+        fix:  bool,
+        /// Output the problems as json
+        #[clap(long)]
+        json: bool,
+    },
+
+    /// Write a new desktop entry to $XDG_DATA_HOME/applications
+    Create {
+        /// Desktop file ID, like btop.desktop
+        id:          String,
+        /// Command to run, with %f/%F for the file(s) or %u/%U for URL(s)
+        #[clap(long, required_unless_present = "interactive")]
+        exec:        Option<String>,
+        /// Name shown in selectors, the ID by default
+        #[clap(long = "name")]
+        app_name:    Option<String>,
+        /// Run the command in a terminal
+        #[clap(long)]
+        terminal:    bool,
+        /// Mime/extension the entry handles, can be repeated
+        #[clap(long = "mime", multiple_occurrences = true, number_of_values = 1)]
+        mimes:       Vec<MimeOrExtension>,
+        /// Also make the entry the default handler of its mimes
+        #[clap(long)]
+        set_default: bool,
+        /// Ask for each field, offering the given ones as defaults
+        #[clap(long, short)]
+        interactive: bool,
+        /// Replace an existing desktop file
+        #[clap(long)]
+        force:       bool,
+    },
+
+    /// Manage the index of parsed desktop files
+    Cache {
+        #[clap(subcommand)]
+        action: CacheCmd,
+    },
+
     /// Edit a desktop file in $EDITOR
     Edit { handler: Handler },
 
-    /// Display a desktop file in the terminal
-    Cat { handler: Handler },
+    /// Display a desktop file in the terminal, along with the ones it shadows
+    Cat {
+        handler:   Handler,
+        /// Only show the entry handlr uses, as it understands it
+        #[clap(long)]
+        effective: bool,
+    },
 
     /// Get the status of whether or not the desktop file is in use
     Status { handler: Handler },
@@ -63,10 +183,10 @@ pub(crate) enum Cmd {
     Ask {
         /// File path to open
         #[clap(required = true)]
-        path:   UserPath,
+        path:           UserPath,
         /// Use skim as a selector
         #[clap(name = "skim", short = 's', long = "skim", takes_value = false)]
-        skim:   bool,
+        skim:           bool,
         /// Use plain text to the TUI as a selector (default unless config file
         /// allows selector)
         #[clap(
@@ -76,10 +196,20 @@ pub(crate) enum Cmd {
             takes_value = false,
             conflicts_with_all = &["skim", "config"],
         )]
-        plain:  bool,
+        plain:          bool,
         /// Use selector that is in the configuration file
         #[clap(name = "config", short = 'c', long = "config", takes_value = false)]
-        config: bool,
+        config:         bool,
+        /// Also offer apps that are hidden, not installed or meant for other
+        /// desktops
+        #[clap(long)]
+        include_hidden: bool,
+        /// Print the commands that would run instead of running them
+        #[clap(long)]
+        dry_run:        bool,
+        /// Print the commands of --dry-run as json
+        #[clap(long, requires = "dry-run")]
+        json:           bool,
     },
 
     #[clap(setting = clap::AppSettings::Hidden)]
@@ -90,3 +220,39 @@ pub(crate) enum Cmd {
         mimes:         bool,
     },
 }
+
+#[derive(clap::Subcommand)]
+pub(crate) enum CacheCmd {
+    /// Parse every desktop file again
+    Rebuild,
+    /// Remove the index
+    Clear,
+    /// Show where the index is and what it holds
+    Stats,
+}
+
+impl Cmd {
+    /// Whether the command writes back to the user's `mimeapps.list`
+    pub(crate) fn modifies_mimeapps(&self) -> bool {
+        matches!(
+            self,
+            Self::Set { .. }
+                | Self::Add { .. }
+                | Self::Unset { .. }
+                | Self::RemoveAssociation { .. }
+                | Self::Undo { .. }
+                | Self::RevertTo { .. }
+                | Self::Import { .. }
+                | Self::Apply { check: false, .. }
+                | Self::Lint { fix: true, .. }
+                | Self::Create {
+                    set_default: true,
+                    ..
+                }
+                | Self::Create {
+                    interactive: true,
+                    ..
+                }
+        )
+    }
+}
@@ -0,0 +1,204 @@
+use crate::{
+    common::{exec, DesktopEntry, MimeOrExtension},
+    utils, Error, Result,
+};
+use mime::Mime;
+use std::{convert::TryFrom, path::Path, str::FromStr};
+
+/// A desktop entry to be written by `handlr create`
+#[derive(Debug, Clone, Default)]
+pub(crate) struct NewEntry {
+    /// The desktop file ID, `.desktop` is added if it is missing
+    pub(crate) id:          String,
+    /// Defaults to the ID without `.desktop`
+    pub(crate) name:        Option<String>,
+    pub(crate) exec:        Option<String>,
+    pub(crate) terminal:    bool,
+    pub(crate) mimes:       Vec<Mime>,
+    /// Make the entry the default handler of its mimes
+    pub(crate) set_default: bool,
+}
+
+impl NewEntry {
+    fn file_name(&self) -> String {
+        if self.id.ends_with(".desktop") {
+            self.id.clone()
+        } else {
+            format!("{}.desktop", self.id)
+        }
+    }
+
+    /// Ask for each field, offering the given ones as defaults
+    pub(crate) fn ask(&mut self) -> Result<()> {
+        let file_name = self.file_name();
+        let default_name = file_name.trim_end_matches(".desktop");
+
+        self.name = Some(utils::prompt(
+            "Name",
+            Some(self.name.as_deref().unwrap_or(default_name)),
+        )?);
+
+        loop {
+            let exec = utils::prompt(
+                "Command to run (%f for a file, %F for several)",
+                self.exec.as_deref(),
+            )?;
+            if exec.is_empty() {
+                eprintln!("a command is required");
+                continue;
+            }
+            match exec::validate(&exec) {
+                Ok(()) => {
+                    self.exec = Some(exec);
+                    break;
+                },
+                Err(e) => eprintln!("{e}"),
+            }
+        }
+
+        self.terminal = utils::confirm("Run in a terminal?", self.terminal)?;
+
+        loop {
+            let current = self
+                .mimes
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>();
+            let mimes = utils::prompt(
+                "Mimes or extensions, separated by spaces",
+                Some(&current.join(" ")),
+            )?;
+            match mimes
+                .split_whitespace()
+                .map(|m| MimeOrExtension::from_str(m).map(|m| m.0))
+                .collect()
+            {
+                Ok(mimes) => {
+                    self.mimes = mimes;
+                    break;
+                },
+                Err(e) => eprintln!("{e}"),
+            }
+        }
+
+        if !self.mimes.is_empty() {
+            self.set_default = utils::confirm("Set as default for these?", self.set_default)?;
+        }
+
+        Ok(())
+    }
+
+    /// The entry as it is going to be written to `dir`
+    fn to_entry(&self, dir: &Path) -> Result<DesktopEntry> {
+        let file_name = self.file_name();
+        if file_name.contains('/') || file_name == ".desktop" {
+            return Err(Error::BadPath(self.id.clone()));
+        }
+
+        Ok(DesktopEntry {
+            name: self
+                .name
+                .clone()
+                .unwrap_or_else(|| file_name.trim_end_matches(".desktop").to_owned()),
+            exec: self.exec.clone().unwrap_or_default(),
+            terminal: self.terminal,
+            mimes: self.mimes.clone(),
+            file_path: dir.join(&file_name),
+            file_name: file_name.into(),
+            ..DesktopEntry::default()
+        })
+    }
+
+    /// Write the entry to `$XDG_DATA_HOME/applications`
+    pub(crate) fn write(&self, force: bool) -> Result<DesktopEntry> {
+        let dir = xdg::BaseDirectories::new()?
+            .get_data_home()
+            .join("applications");
+        std::fs::create_dir_all(&dir)?;
+        self.write_to(&dir, force)
+    }
+
+    /// Write the entry to `dir`, but only once it reads back exactly as
+    /// intended
+    fn write_to(&self, dir: &Path, force: bool) -> Result<DesktopEntry> {
+        use std::io::Write;
+
+        let entry = self.to_entry(dir)?;
+        if entry.file_path.exists() && !force {
+            return Err(Error::EntryExists(entry.file_path));
+        }
+
+        let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+        tmp.write_all(entry.render().as_bytes())?;
+
+        let parsed = DesktopEntry::try_from(tmp.path().to_owned())
+            .map_err(|_| Error::BadEntry(entry.file_path.clone()))?;
+        exec::validate(&parsed.exec)?;
+        if (DesktopEntry {
+            file_name: entry.file_name.clone(),
+            file_path: entry.file_path.clone(),
+            ..parsed
+        }) != entry
+        {
+            return Err(Error::BadEntry(entry.file_path));
+        }
+
+        tmp.persist(&entry.file_path).map_err(|e| e.error)?;
+
+        Ok(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_new_entry() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let new = NewEntry {
+            id: "btop".into(),
+            exec: Some("btop".into()),
+            terminal: true,
+            mimes: vec![Mime::from_str("x-scheme-handler/btop")?],
+            ..NewEntry::default()
+        };
+
+        let entry = new.write_to(dir.path(), false)?;
+        assert_eq!(entry.file_name, "btop.desktop");
+        assert_eq!(entry.name, "btop");
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("btop.desktop"))?,
+            "[Desktop Entry]
+Type=Application
+Name=btop
+Exec=btop
+Terminal=true
+MimeType=x-scheme-handler/btop;
+"
+        );
+
+        assert!(matches!(
+            new.write_to(dir.path(), false),
+            Err(Error::EntryExists(_))
+        ));
+        new.write_to(dir.path(), true)?;
+
+        // Values that would not read back the same are refused
+        let refused = |new: NewEntry| new.write_to(dir.path(), true).unwrap_err();
+        refused(NewEntry {
+            name: Some("two\nlines".into()),
+            ..new.clone()
+        });
+        refused(NewEntry {
+            exec: Some("btop \"--unterminated".into()),
+            ..new.clone()
+        });
+        refused(NewEntry {
+            id: "../btop".into(),
+            ..new
+        });
+
+        Ok(())
+    }
+}
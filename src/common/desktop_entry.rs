@@ -1,24 +1,96 @@
-use crate::{Error, Result};
-use aho_corasick::AhoCorasick;
-use freedesktop_entry_parser::Attr;
+use crate::{
+    common::{exec, startup, terminal, terminal::TerminalArgs},
+    config::{Config, LaunchCwd},
+    utils, Error, Result, CONFIG,
+};
+use freedesktop_entry_parser::{Attr, AttrSelector};
 use mime::Mime;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::HashSet,
     convert::TryFrom,
     ffi::OsString,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
     process::{Command, Stdio},
     str::FromStr,
 };
 
-#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub(crate) struct DesktopEntry {
-    pub(crate) name:       String,
-    pub(crate) exec:       String,
-    pub(crate) file_name:  OsString,
-    pub(crate) terminal:   bool,
-    pub(crate) mimes:      Vec<Mime>,
-    pub(crate) categories: HashSet<String>,
+    /// Localized for the messages locale, like `generic_name` and `comment`
+    pub(crate) name:             String,
+    pub(crate) generic_name:     Option<String>,
+    pub(crate) comment:          Option<String>,
+    pub(crate) exec:             String,
+    pub(crate) icon:             Option<String>,
+    /// The `Path=` the entry wants to run in
+    pub(crate) working_dir:      Option<PathBuf>,
+    /// The desktop file ID, see `desktop_id`
+    pub(crate) file_name:        OsString,
+    /// Where the desktop file was read from
+    pub(crate) file_path:        PathBuf,
+    pub(crate) terminal:         bool,
+    #[serde(with = "mimes")]
+    pub(crate) mimes:            Vec<Mime>,
+    pub(crate) categories:       HashSet<String>,
+    /// The `[Desktop Action]` groups listed in `Actions=`
+    pub(crate) actions:          Vec<Action>,
+    /// The action this entry was narrowed down to with `with_action`
+    pub(crate) action:           Option<String>,
+    /// The entry was deleted and must be treated as if it did not exist
+    pub(crate) hidden:           bool,
+    /// The entry can handle mimes, but should not be offered to the user
+    pub(crate) no_display:       bool,
+    /// A binary the entry is ignored without
+    pub(crate) try_exec:         Option<String>,
+    pub(crate) only_show_in:     Vec<String>,
+    pub(crate) not_show_in:      Vec<String>,
+    /// The app announces when it has started, see the `startup` module
+    pub(crate) startup_notify:   bool,
+    /// The `WM_CLASS` of the window the app opens
+    pub(crate) startup_wm_class: Option<String>,
+    /// Set for terminal emulators, see the `terminal` module
+    pub(crate) terminal_args:    TerminalArgs,
+}
+
+/// An additional way to start an application, like opening a private window
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Action {
+    pub(crate) id:   String,
+    /// Localized like the name of the entry
+    pub(crate) name: String,
+    pub(crate) exec: String,
+}
+
+/// A command line an entry is run with
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub(crate) struct Invocation {
+    pub(crate) argv: Vec<String>,
+    /// Where the command runs, unless it is where handlr runs
+    pub(crate) dir:  Option<PathBuf>,
+    /// The binary of the app itself, even if it runs under a launch wrapper
+    #[serde(skip)]
+    bin:             String,
+}
+
+impl Invocation {
+    /// Print the command lines for a dry run, as shell commands or as json
+    pub(crate) fn print_all(invocations: &[Self], json: bool) -> Result<()> {
+        if json {
+            println!("{}", serde_json::to_string_pretty(invocations)?);
+            return Ok(());
+        }
+
+        for invocation in invocations {
+            let cmd = shlex::join(invocation.argv.iter().map(String::as_str));
+            match &invocation.dir {
+                Some(dir) => println!("cd {} && {cmd}", shlex::quote(&dir.to_string_lossy())),
+                None => println!("{cmd}"),
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(PartialEq, Eq, Copy, Clone)]
@@ -28,96 +100,466 @@ pub(crate) enum Mode {
 }
 
 impl DesktopEntry {
-    pub(crate) fn exec(&self, mode: Mode, arguments: Vec<String>) -> Result<()> {
-        let supports_multiple = self.exec.contains("%F") || self.exec.contains("%U");
-        if arguments.is_empty() {
-            self.exec_inner(vec![])?;
-        } else if supports_multiple || mode == Mode::Launch {
-            self.exec_inner(arguments)?;
+    /// The entry running one of its actions instead, named `App › Action`
+    pub(crate) fn with_action(&self, id: &str) -> Result<Self> {
+        let action = self.actions.iter().find(|a| a.id == id).ok_or_else(|| {
+            Error::UnknownAction(self.file_name.to_string_lossy().into(), id.to_owned())
+        })?;
+
+        Ok(Self {
+            name: format!("{} › {}", self.name, action.name),
+            exec: action.exec.clone(),
+            actions: vec![],
+            action: Some(action.id.clone()),
+            ..self.clone()
+        })
+    }
+
+    /// The entry followed by one entry for each of its actions
+    pub(crate) fn with_actions(&self) -> impl Iterator<Item = Self> + '_ {
+        std::iter::once(self.clone()).chain(
+            self.actions
+                .iter()
+                .filter_map(move |a| self.with_action(&a.id).ok()),
+        )
+    }
+
+    /// Whether the entry can be used at all, which it cannot if it is
+    /// `Hidden` or its `TryExec` binary is not installed
+    pub(crate) fn is_usable(&self) -> bool {
+        !self.hidden
+            && self
+                .try_exec
+                .as_ref()
+                .is_none_or(|bin| which::which(bin).is_ok())
+    }
+
+    /// Whether `OnlyShowIn` and `NotShowIn` allow the entry in one of the
+    /// current desktops
+    pub(crate) fn is_shown_in(&self, desktops: &[String]) -> bool {
+        let listed = |list: &[String]| desktops.iter().any(|d| list.contains(d));
+
+        (self.only_show_in.is_empty() || listed(&self.only_show_in)) && !listed(&self.not_show_in)
+    }
+
+    /// Whether the entry should be offered to the user, in selectors and
+    /// listings
+    pub(crate) fn should_show(&self, desktops: &[String]) -> bool {
+        self.is_usable() && !self.no_display && self.is_shown_in(desktops)
+    }
+
+    /// What tells the entry apart from the others in a selector
+    pub(crate) fn selector_id(&self) -> String {
+        let file_name = self.file_name.to_string_lossy().replace('"', "");
+        match &self.action {
+            Some(action) => format!("{file_name} › {action}"),
+            None => file_name,
+        }
+    }
+
+    /// The entry as a desktop file with only the keys handlr understands, in
+    /// the values it uses
+    pub(crate) fn render(&self) -> String {
+        let list = |items: &[String]| format!("{};", items.join(";"));
+        let flag = |key: &str, set: bool| set.then(|| format!("{key}=true"));
+
+        let mut categories = self.categories.iter().cloned().collect::<Vec<_>>();
+        categories.sort();
+        let mimes = self
+            .mimes
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let action_ids = self
+            .actions
+            .iter()
+            .map(|a| a.id.clone())
+            .collect::<Vec<_>>();
+
+        let mut lines = vec![
+            Some("[Desktop Entry]".to_owned()),
+            Some("Type=Application".to_owned()),
+            Some(format!("Name={}", self.name)),
+            self.generic_name
+                .as_ref()
+                .map(|n| format!("GenericName={n}")),
+            self.comment.as_ref().map(|c| format!("Comment={c}")),
+            Some(format!("Exec={}", self.exec)),
+            self.try_exec.as_ref().map(|t| format!("TryExec={t}")),
+            self.working_dir
+                .as_ref()
+                .map(|p| format!("Path={}", p.display())),
+            self.icon.as_ref().map(|i| format!("Icon={i}")),
+            Some(format!("Terminal={}", self.terminal)),
+            (!mimes.is_empty()).then(|| format!("MimeType={}", list(&mimes))),
+            (!categories.is_empty()).then(|| format!("Categories={}", list(&categories))),
+            (!self.only_show_in.is_empty())
+                .then(|| format!("OnlyShowIn={}", list(&self.only_show_in))),
+            (!self.not_show_in.is_empty())
+                .then(|| format!("NotShowIn={}", list(&self.not_show_in))),
+            flag("Hidden", self.hidden),
+            flag("NoDisplay", self.no_display),
+            flag("StartupNotify", self.startup_notify),
+            self.startup_wm_class
+                .as_ref()
+                .map(|c| format!("StartupWMClass={c}")),
+            (!action_ids.is_empty()).then(|| format!("Actions={}", list(&action_ids))),
+        ];
+        let args = &self.terminal_args;
+        for (key, value) in [
+            ("Exec", &args.exec),
+            ("AppId", &args.app_id),
+            ("Title", &args.title),
+            ("Dir", &args.dir),
+            ("Hold", &args.hold),
+        ] {
+            lines.push(value.as_ref().map(|v| format!("X-TerminalArg{key}={v}")));
+        }
+
+        for action in &self.actions {
+            lines.extend([
+                Some(String::new()),
+                Some(format!("[Desktop Action {}]", action.id)),
+                Some(format!("Name={}", action.name)),
+                Some(format!("Exec={}", action.exec)),
+            ]);
+        }
+
+        lines
+            .into_iter()
+            .flatten()
+            .map(|line| line + "\n")
+            .collect()
+    }
+
+    /// The command lines that open the given files or URLs, which are of
+    /// type `mime`. Apps that take a single file at a time are run once for
+    /// each. For a `dry_run`, nothing is written while looking them up.
+    pub(crate) fn invocations(
+        &self,
+        mode: Mode,
+        arguments: Vec<String>,
+        mime: Option<&Mime>,
+        dry_run: bool,
+    ) -> Result<Vec<Invocation>> {
+        let batches = if arguments.is_empty() {
+            vec![vec![]]
+        } else if exec::takes_multiple(&self.exec) || mode == Mode::Launch {
+            vec![arguments]
         } else {
-            for arg in arguments {
-                self.exec_inner(vec![arg])?;
-            }
+            arguments.into_iter().map(|arg| vec![arg]).collect()
         };
 
+        batches
+            .into_iter()
+            .map(|args| self.invocation(&CONFIG, args, mime, dry_run))
+            .collect()
+    }
+
+    /// Run the entry with the given files or URLs, which are of type `mime`
+    pub(crate) fn exec(
+        &self,
+        mode: Mode,
+        arguments: Vec<String>,
+        mime: Option<&Mime>,
+    ) -> Result<()> {
+        for invocation in self.invocations(mode, arguments, mime, false)? {
+            self.run(invocation)?;
+        }
+
         Ok(())
     }
 
-    fn exec_inner(&self, args: Vec<String>) -> Result<()> {
-        let mut cmd = {
-            let (cmd, args) = self.get_cmd(args)?;
-            let mut cmd = Command::new(cmd);
-            cmd.args(args);
-            cmd
+    /// The directory to run the entry in when opening `args`. A handler
+    /// specific setting wins over `Path=`, which wins over the global one.
+    fn launch_dir(&self, config: &Config, args: &[String]) -> Option<PathBuf> {
+        let file_dir = || {
+            let path = std::env::current_dir()
+                .ok()?
+                .join(exec::local_path(args.first()?)?);
+            if path.is_dir() {
+                Some(path)
+            } else {
+                path.parent().map(ToOwned::to_owned)
+            }
+        };
+        let resolve = |cwd| match cwd {
+            LaunchCwd::Inherit => None,
+            LaunchCwd::FileDir => file_dir(),
         };
 
-        if self.terminal && atty::is(atty::Stream::Stdout) {
-            cmd.spawn()?.wait()?;
-        } else {
-            cmd.stdout(Stdio::null()).stderr(Stdio::null()).spawn()?;
+        match config
+            .handler_launch_cwd
+            .get(self.file_name.to_string_lossy().as_ref())
+        {
+            Some(&cwd) => resolve(cwd),
+            None => self
+                .working_dir
+                .clone()
+                .or_else(|| resolve(config.launch_cwd)),
         }
-
-        Ok(())
     }
 
-    #[allow(clippy::needless_pass_by_value)]
-    pub(crate) fn get_cmd(&self, args: Vec<String>) -> Result<(String, Vec<String>)> {
-        let special = AhoCorasick::new_auto_configured(&["%f", "%F", "%u", "%U"]);
+    /// The launch wrapper configured for the entry, with its placeholders
+    /// filled in
+    fn wrapper(&self, config: &Config, mime: Option<&Mime>) -> Result<Vec<String>> {
+        let file_name = self.file_name.to_string_lossy();
+        let Some(template) = config.launch_wrapper(&file_name, mime) else {
+            return Ok(vec![]);
+        };
+        let app_id = file_name.trim_end_matches(".desktop");
+
+        Ok(shlex::split(template)
+            .ok_or_else(|| Error::BadWrapper(template.to_owned()))?
+            .into_iter()
+            .map(|arg| {
+                arg.replace("{app_id}", app_id)
+                    .replace("{name}", &self.name)
+            })
+            .collect())
+    }
 
-        let mut exec = shlex::split(&self.exec).unwrap();
+    /// The command line that runs the entry with `args`
+    fn invocation(
+        &self,
+        config: &Config,
+        args: Vec<String>,
+        mime: Option<&Mime>,
+        dry_run: bool,
+    ) -> Result<Invocation> {
+        let dir = self.launch_dir(config, &args);
 
-        // The desktop entry doesn't contain arguments - we make best effort and append
-        // them at the end
-        if special.is_match(&self.exec) {
-            exec = exec
+        // Relative paths would point elsewhere once the app runs in another
+        // directory
+        let args = match &dir {
+            Some(_) => args
                 .into_iter()
-                .flat_map(|s| match s.as_str() {
-                    "%f" | "%F" | "%u" | "%U" => args.clone(),
-                    s if special.is_match(s) => vec![{
-                        let mut replaced = String::with_capacity(s.len() + args.len() * 2);
-                        special.replace_all_with(s, &mut replaced, |_, _, dst| {
-                            dst.push_str(args.clone().join(" ").as_str());
-                            false
-                        });
-                        replaced
-                    }],
-                    _ => vec![s],
+                .map(|arg| match exec::local_path(&arg) {
+                    Some(path) if path.is_relative() => std::env::current_dir()
+                        .map_or(arg, |cwd| cwd.join(path).to_string_lossy().into_owned()),
+                    _ => arg,
                 })
-                .collect();
+                .collect(),
+            None => args,
+        };
+
+        let (program, args) = self.get_cmd(args, dir.as_deref(), dry_run)?;
+        let mut argv = self.wrapper(config, mime)?;
+        argv.push(program.clone());
+        argv.extend(args);
+
+        Ok(Invocation {
+            argv,
+            dir,
+            bin: program,
+        })
+    }
+
+    /// The log the output of the entry is appended to, starting with a line
+    /// about this launch
+    fn log_file(&self, cmd: &Command) -> Result<std::fs::File> {
+        use std::io::Write;
+
+        let path = xdg::BaseDirectories::with_prefix("handlr")?.place_state_file(format!(
+            "logs/{}.log",
+            self.file_name
+                .to_string_lossy()
+                .trim_end_matches(".desktop")
+        ))?;
+        let mut log = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        writeln!(
+            log,
+            "--- {} {cmd:?}",
+            humantime::format_rfc3339_seconds(std::time::SystemTime::now())
+        )?;
+
+        Ok(log)
+    }
+
+    fn run(&self, invocation: Invocation) -> Result<()> {
+        let mut argv = invocation.argv;
+        let mut cmd = Command::new(argv.remove(0));
+        cmd.args(argv);
+        if let Some(dir) = invocation.dir {
+            cmd.current_dir(dir);
+        }
+        let startup_id = startup::prepare(&mut cmd, self, &invocation.bin);
+
+        let spawned = self.spawn(&mut cmd);
+        if let (Err(_), Some(id)) = (&spawned, startup_id) {
+            startup::cancel(&id);
+        }
+
+        spawned
+    }
+
+    fn spawn(&self, cmd: &mut Command) -> Result<()> {
+        // Terminal apps run in the terminal handlr was started from
+        if self.terminal && atty::is(atty::Stream::Stdout) {
+            cmd.spawn()?.wait()?;
+            return Ok(());
+        }
+
+        cmd.stdin(Stdio::null());
+        if CONFIG.launch_logs {
+            let log = self.log_file(cmd)?;
+            cmd.stdout(log.try_clone()?).stderr(log);
         } else {
-            exec.extend_from_slice(&args);
+            cmd.stdout(Stdio::null()).stderr(Stdio::null());
         }
 
+        utils::spawn_detached(cmd)
+    }
+
+    #[allow(clippy::needless_pass_by_value)]
+    pub(crate) fn get_cmd(
+        &self,
+        args: Vec<String>,
+        dir: Option<&Path>,
+        dry_run: bool,
+    ) -> Result<(String, Vec<String>)> {
+        let mut exec = exec::expand(self, &args)?;
+
         // If the entry expects a terminal (emulator), but this process is not running
         // in one, we launch a new one.
         if self.terminal && !atty::is(atty::Stream::Stdout) {
-            exec = shlex::split(&crate::config::Config::terminal()?)
-                .unwrap()
-                .into_iter()
-                .chain(vec!["-e".to_owned()])
-                .chain(exec)
-                .collect();
+            exec = terminal::command(
+                &Config::terminal(!dry_run)?,
+                self,
+                dir,
+                exec,
+                CONFIG.hold_terminal,
+            )?;
         }
 
         Ok((exec.remove(0), exec))
     }
 }
 
-fn parse_file(path: &Path) -> Option<DesktopEntry> {
+/// (De)serialize mimes as plain strings, since `Mime` does not implement serde
+mod mimes {
+    use mime::Mime;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<S: Serializer>(mimes: &[Mime], s: S) -> Result<S::Ok, S::Error> {
+        s.collect_seq(mimes.iter().map(AsRef::<str>::as_ref))
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<Mime>, D::Error> {
+        Vec::<String>::deserialize(d)?
+            .iter()
+            .map(|m| m.parse().map_err(D::Error::custom))
+            .collect()
+    }
+}
+
+/// The messages locale as `lang_COUNTRY@MODIFIER`, without the encoding, or
+/// `None` for the C locale
+pub(crate) fn locale() -> Option<String> {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())?;
+
+    let (name, modifier) = match locale.split_once('@') {
+        Some((name, modifier)) => (name, Some(modifier)),
+        None => (locale.as_str(), None),
+    };
+    let name = name.split('.').next().unwrap_or_default();
+
+    match (name, modifier) {
+        ("" | "C" | "POSIX", _) => None,
+        (name, Some(modifier)) => Some(format!("{name}@{modifier}")),
+        (name, None) => Some(name.to_owned()),
+    }
+}
+
+/// The locale keys to look localized values up by, in the order the desktop
+/// entry spec matches them
+fn locale_keys(locale: &str) -> Vec<String> {
+    let (name, modifier) = match locale.split_once('@') {
+        Some((name, modifier)) => (name, Some(modifier)),
+        None => (locale, None),
+    };
+    let (lang, country) = match name.split_once('_') {
+        Some((lang, country)) => (lang, Some(country)),
+        None => (name, None),
+    };
+
+    let mut keys = vec![];
+    if let (Some(country), Some(modifier)) = (country, modifier) {
+        keys.push(format!("{lang}_{country}@{modifier}"));
+    }
+    if let Some(country) = country {
+        keys.push(format!("{lang}_{country}"));
+    }
+    if let Some(modifier) = modifier {
+        keys.push(format!("{lang}@{modifier}"));
+    }
+    keys.push(lang.to_owned());
+    keys
+}
+
+/// The value of `name` in the best matching locale
+fn localize<T: AsRef<str>>(
+    section: &AttrSelector<'_, T>,
+    keys: &[String],
+    name: &str,
+) -> Option<String> {
+    keys.iter()
+        .find_map(|key| section.attr_with_param(name, key))
+        .or_else(|| section.attr(name))
+        .map(ToOwned::to_owned)
+}
+
+/// The desktop file ID of the file at `path`, which is its path below the
+/// `applications` directory with dashes instead of slashes, like
+/// `kde4-okular.desktop` for `applications/kde4/okular.desktop`
+pub(crate) fn desktop_id(path: &Path) -> Option<OsString> {
+    let components = path
+        .components()
+        .map(Component::as_os_str)
+        .collect::<Vec<_>>();
+    let (file_name, parents) = components.split_last()?;
+    let start = parents
+        .iter()
+        .rposition(|c| *c == "applications")
+        .map_or(parents.len(), |i| i + 1);
+
+    let mut id = OsString::new();
+    for parent in &parents[start..] {
+        id.push(parent);
+        id.push("-");
+    }
+    id.push(file_name);
+
+    Some(id)
+}
+
+fn parse_file(path: &Path, locale: Option<&str>) -> Option<DesktopEntry> {
     let raw_entry = freedesktop_entry_parser::parse_entry(&path).ok()?;
     let section = raw_entry.section("Desktop Entry");
 
+    let keys = locale.map(locale_keys).unwrap_or_default();
+    let localized = |name: &str| localize(&section, &keys, name);
+
     let mut entry = DesktopEntry {
-        file_name: path.file_name()?.to_owned(),
+        name: localized("Name").unwrap_or_default(),
+        generic_name: localized("GenericName"),
+        comment: localized("Comment"),
+        file_name: desktop_id(path)?,
+        file_path: path.to_owned(),
         ..DesktopEntry::default()
     };
 
     for attr in section.attrs().into_iter().filter(Attr::has_value) {
         match attr.name {
-            "Name" if entry.name.is_empty() => {
-                entry.name = attr.value.unwrap().into();
-            },
             "Exec" => entry.exec = attr.value.unwrap().into(),
+            "Icon" => entry.icon = Some(attr.value.unwrap().into()),
+            "Path" => entry.working_dir = Some(attr.value.unwrap().into()),
             "MimeType" => {
                 entry.mimes = attr
                     .value
@@ -127,6 +569,31 @@ fn parse_file(path: &Path) -> Option<DesktopEntry> {
                     .collect::<Vec<_>>();
             },
             "Terminal" => entry.terminal = attr.value.unwrap() == "true",
+            "Hidden" => entry.hidden = attr.value.unwrap() == "true",
+            "NoDisplay" => entry.no_display = attr.value.unwrap() == "true",
+            "TryExec" => entry.try_exec = Some(attr.value.unwrap().into()),
+            "StartupNotify" => entry.startup_notify = attr.value.unwrap() == "true",
+            "StartupWMClass" => entry.startup_wm_class = Some(attr.value.unwrap().into()),
+            "X-TerminalArgExec" => entry.terminal_args.exec = Some(attr.value.unwrap().into()),
+            "X-TerminalArgAppId" => entry.terminal_args.app_id = Some(attr.value.unwrap().into()),
+            "X-TerminalArgTitle" => entry.terminal_args.title = Some(attr.value.unwrap().into()),
+            "X-TerminalArgDir" => entry.terminal_args.dir = Some(attr.value.unwrap().into()),
+            "X-TerminalArgHold" => entry.terminal_args.hold = Some(attr.value.unwrap().into()),
+            "OnlyShowIn" | "NotShowIn" => {
+                let desktops = attr
+                    .value
+                    .unwrap()
+                    .split(';')
+                    .filter(|s| !s.is_empty())
+                    .map(ToOwned::to_owned)
+                    .collect();
+
+                if attr.name == "OnlyShowIn" {
+                    entry.only_show_in = desktops;
+                } else {
+                    entry.not_show_in = desktops;
+                }
+            },
             "Categories" => {
                 entry.categories = attr
                     .value
@@ -136,6 +603,22 @@ fn parse_file(path: &Path) -> Option<DesktopEntry> {
                     .map(ToOwned::to_owned)
                     .collect();
             },
+            "Actions" => {
+                entry.actions = attr
+                    .value
+                    .unwrap()
+                    .split(';')
+                    .filter(|s| !s.is_empty())
+                    .filter_map(|id| {
+                        let action = raw_entry.section(format!("Desktop Action {id}"));
+                        Some(Action {
+                            id:   id.to_owned(),
+                            name: localize(&action, &keys, "Name")?,
+                            exec: action.attr("Exec")?.to_owned(),
+                        })
+                    })
+                    .collect();
+            },
             _ => {},
         }
     }
@@ -151,7 +634,7 @@ impl TryFrom<PathBuf> for DesktopEntry {
     type Error = Error;
 
     fn try_from(path: PathBuf) -> Result<DesktopEntry> {
-        parse_file(&path).ok_or(Error::BadEntry(path))
+        parse_file(&path, locale().as_deref()).ok_or(Error::BadEntry(path))
     }
 }
 
@@ -161,9 +644,238 @@ mod tests {
 
     #[test]
     fn complex_exec() {
-        let entry = parse_file(Path::new("tests/cmus.desktop")).unwrap();
+        let entry = parse_file(Path::new("tests/cmus.desktop"), None).unwrap();
         assert_eq!(entry.mimes.len(), 2);
         assert_eq!(entry.mimes[0].essence_str(), "audio/mp3");
         assert_eq!(entry.mimes[1].essence_str(), "audio/ogg");
     }
+
+    #[test]
+    fn desktop_actions() -> Result<()> {
+        let entry = parse_file(Path::new("tests/firefox.desktop"), Some("de_DE")).unwrap();
+        assert_eq!(
+            entry
+                .actions
+                .iter()
+                .map(|a| a.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["new-window", "new-private-window"]
+        );
+
+        let private = entry.with_action("new-private-window")?;
+        assert_eq!(private.name, "Firefox › Neues privates Fenster");
+        assert_eq!(private.exec, "firefox --private-window %u");
+        assert_eq!(
+            private.selector_id(),
+            "firefox.desktop › new-private-window"
+        );
+        assert_eq!(entry.with_actions().count(), 3);
+        entry.with_action("profile-manager").unwrap_err();
+
+        Ok(())
+    }
+
+    #[test]
+    fn render_effective() {
+        let entry = parse_file(Path::new("tests/firefox.desktop"), Some("de_DE")).unwrap();
+        assert_eq!(
+            entry.render(),
+            "[Desktop Entry]
+Type=Application
+Name=Firefox
+GenericName=Web Browser
+Exec=firefox %u
+Terminal=false
+MimeType=text/html;x-scheme-handler/http;x-scheme-handler/https;
+Categories=Network;WebBrowser;
+StartupNotify=true
+StartupWMClass=firefox
+Actions=new-window;new-private-window;
+
+[Desktop Action new-window]
+Name=Neues Fenster
+Exec=firefox --new-window %u
+
+[Desktop Action new-private-window]
+Name=Neues privates Fenster
+Exec=firefox --private-window %u
+"
+        );
+    }
+
+    #[test]
+    fn visibility() {
+        let entry = parse_file(Path::new("tests/gnome-only.desktop"), None).unwrap();
+        let desktops = |d: &[&str]| d.iter().map(|d| (*d).to_owned()).collect::<Vec<_>>();
+
+        assert!(entry.is_usable());
+        assert!(entry.is_shown_in(&desktops(&["GNOME"])));
+        assert!(!entry.is_shown_in(&desktops(&["sway"])));
+        assert!(!entry.is_shown_in(&desktops(&["GNOME", "Unity"])));
+        assert!(!entry.should_show(&desktops(&["GNOME"])));
+
+        let missing = DesktopEntry {
+            try_exec: Some("handlr-test-not-installed".into()),
+            ..entry.clone()
+        };
+        assert!(!missing.is_usable());
+        assert!(!DesktopEntry {
+            hidden: true,
+            ..entry
+        }
+        .is_usable());
+    }
+
+    #[test]
+    fn launch_dir() {
+        let mut config = Config::default();
+        let mut entry = DesktopEntry {
+            file_name: "nvim.desktop".into(),
+            ..DesktopEntry::default()
+        };
+        let args = vec!["/etc/hosts".to_owned()];
+
+        assert_eq!(entry.launch_dir(&config, &args), None);
+
+        config.launch_cwd = LaunchCwd::FileDir;
+        assert_eq!(entry.launch_dir(&config, &args), Some("/etc".into()));
+        assert_eq!(
+            entry.launch_dir(&config, &["/etc".into()]),
+            Some("/etc".into())
+        );
+        assert_eq!(
+            entry.launch_dir(&config, &["https://example.com".into()]),
+            None
+        );
+
+        entry.working_dir = Some("/opt/nvim".into());
+        assert_eq!(entry.launch_dir(&config, &args), Some("/opt/nvim".into()));
+
+        config
+            .handler_launch_cwd
+            .insert("nvim.desktop".into(), LaunchCwd::Inherit);
+        assert_eq!(entry.launch_dir(&config, &args), None);
+    }
+
+    #[test]
+    fn dry_run_invocations() -> Result<()> {
+        let entry = DesktopEntry {
+            name: "mpv".into(),
+            exec: "mpv --player-operation-mode=pseudo-gui -- %f".into(),
+            file_name: "mpv.desktop".into(),
+            ..DesktopEntry::default()
+        };
+        let args = vec!["/tmp/a b.mkv".to_owned(), "/tmp/c.mkv".to_owned()];
+
+        // One file at a time, unless launched or the app takes several
+        let argvs = |entry: &DesktopEntry, mode| -> Result<Vec<Vec<String>>> {
+            Ok(entry
+                .invocations(mode, args.clone(), None, true)?
+                .into_iter()
+                .map(|i| i.argv)
+                .collect())
+        };
+        assert_eq!(argvs(&entry, Mode::Open)?, vec![
+            vec![
+                "mpv",
+                "--player-operation-mode=pseudo-gui",
+                "--",
+                "/tmp/a b.mkv"
+            ],
+            vec![
+                "mpv",
+                "--player-operation-mode=pseudo-gui",
+                "--",
+                "/tmp/c.mkv"
+            ],
+        ]);
+        assert_eq!(argvs(&entry, Mode::Launch)?.len(), 1);
+        let multiple = DesktopEntry {
+            exec: "mpv %F".into(),
+            ..entry.clone()
+        };
+        assert_eq!(argvs(&multiple, Mode::Open)?, vec![vec![
+            "mpv",
+            "/tmp/a b.mkv",
+            "/tmp/c.mkv"
+        ]]);
+        assert_eq!(entry.invocations(Mode::Open, vec![], None, true)?.len(), 1);
+
+        let invocation = Invocation {
+            argv: vec!["mpv".into(), "/tmp/a b.mkv".into()],
+            dir:  Some("/tmp".into()),
+            bin:  "mpv".into(),
+        };
+        assert_eq!(
+            serde_json::to_value(&invocation)?,
+            serde_json::json!({ "argv": ["mpv", "/tmp/a b.mkv"], "dir": "/tmp" })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn launch_wrapper() -> Result<()> {
+        let mut config = Config {
+            launch_wrapper: Some("systemd-run --user --scope --unit=app-{app_id}".into()),
+            ..Config::default()
+        };
+        let entry = DesktopEntry {
+            name: "Firefox Nightly".into(),
+            file_name: "firefox.desktop".into(),
+            ..DesktopEntry::default()
+        };
+        let video = Mime::from_str("video/mp4")?;
+
+        assert_eq!(entry.wrapper(&config, None)?, vec![
+            "systemd-run",
+            "--user",
+            "--scope",
+            "--unit=app-firefox"
+        ]);
+
+        config
+            .mime_launch_wrapper
+            .insert("video/*".into(), "firejail '--name={name}'".into());
+        assert_eq!(entry.wrapper(&config, Some(&video))?, vec![
+            "firejail",
+            "--name=Firefox Nightly"
+        ]);
+
+        config
+            .handler_launch_wrapper
+            .insert("firefox.desktop".into(), String::new());
+        assert!(entry.wrapper(&config, Some(&video))?.is_empty());
+
+        config.launch_wrapper = Some("bwrap \"--unterminated".into());
+        config.handler_launch_wrapper.clear();
+        entry.wrapper(&config, None).unwrap_err();
+
+        Ok(())
+    }
+
+    #[test]
+    fn localized_keys() {
+        assert_eq!(locale_keys("sr_YU@Latn"), vec![
+            "sr_YU@Latn",
+            "sr_YU",
+            "sr@Latn",
+            "sr"
+        ]);
+
+        let name = |locale| parse_file(Path::new("tests/localized.desktop"), locale).unwrap();
+        assert_eq!(name(None).name, "Files");
+        assert_eq!(name(Some("de_DE")).name, "Dateien");
+        assert_eq!(name(Some("de_AT")).name, "Dateien");
+        assert_eq!(name(Some("pt_BR")).name, "Arquivos");
+        assert_eq!(name(Some("pt_PT")).name, "Ficheiros");
+        assert_eq!(
+            name(Some("ja_JP")).comment.as_deref(),
+            Some("ファイルを閲覧します")
+        );
+        assert_eq!(
+            name(Some("ja_JP")).generic_name.as_deref(),
+            Some("File Manager")
+        );
+    }
 }
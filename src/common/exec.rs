@@ -0,0 +1,339 @@
+//! Parsing and expansion of `Exec` lines, following the [desktop entry spec]
+//!
+//! [desktop entry spec]: https://specifications.freedesktop.org/desktop-entry-spec/latest/ar01s07.html
+
+use crate::{common::DesktopEntry, Error, Result};
+use std::path::PathBuf;
+use url::Url;
+
+/// A single argument of an `Exec` line
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Arg {
+    text:   String,
+    /// Field codes are only expanded to several arguments when they make up a
+    /// whole argument that is not quoted
+    quoted: bool,
+}
+
+/// Undo the escapes every string value of a desktop entry can contain
+fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('s') => out.push(' '),
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('\\') | None => out.push('\\'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            },
+        }
+    }
+
+    out
+}
+
+/// Split an `Exec` line into arguments. Besides the double quotes of the spec,
+/// this accepts the single quotes and backslash escapes plenty of real-world
+/// desktop files use.
+fn split(exec: &str) -> Result<Vec<Arg>> {
+    let exec = unescape(exec);
+    let malformed = || Error::BadExec(exec.clone());
+
+    let mut args = vec![];
+    let mut chars = exec.chars().peekable();
+
+    loop {
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut arg = Arg {
+            text:   String::new(),
+            quoted: false,
+        };
+
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+            match c {
+                '"' => {
+                    arg.quoted = true;
+                    loop {
+                        match chars.next().ok_or_else(malformed)? {
+                            '"' => break,
+                            '\\' => match chars.next().ok_or_else(malformed)? {
+                                c @ ('"' | '`' | '$' | '\\') => arg.text.push(c),
+                                c => {
+                                    arg.text.push('\\');
+                                    arg.text.push(c);
+                                },
+                            },
+                            c => arg.text.push(c),
+                        }
+                    }
+                },
+                '\'' => {
+                    arg.quoted = true;
+                    loop {
+                        match chars.next().ok_or_else(malformed)? {
+                            '\'' => break,
+                            c => arg.text.push(c),
+                        }
+                    }
+                },
+                '\\' => arg.text.push(chars.next().ok_or_else(malformed)?),
+                c => arg.text.push(c),
+            }
+        }
+
+        args.push(arg);
+    }
+
+    if args.is_empty() {
+        return Err(malformed());
+    }
+
+    Ok(args)
+}
+
+/// Check that an `Exec` line can be split into arguments
+pub(crate) fn validate(exec: &str) -> Result<()> {
+    split(exec).map(drop)
+}
+
+/// The field codes in an argument, skipping escaped `%%`
+fn field_codes(text: &str) -> impl Iterator<Item = char> + '_ {
+    let mut chars = text.chars();
+
+    std::iter::from_fn(move || loop {
+        if chars.next()? == '%' {
+            let code = chars.next()?;
+            if code != '%' {
+                return Some(code);
+            }
+        }
+    })
+}
+
+/// Whether the application accepts several files or URLs at once
+pub(crate) fn takes_multiple(exec: &str) -> bool {
+    field_codes(exec).any(|c| matches!(c, 'F' | 'U'))
+}
+
+/// A path or URL as a URL, turning local paths into `file://` URIs
+fn to_url(arg: &str) -> String {
+    if Url::parse(arg).is_ok() {
+        return arg.to_owned();
+    }
+
+    std::env::current_dir()
+        .ok()
+        .and_then(|cwd| Url::from_file_path(cwd.join(arg)).ok())
+        .map_or_else(|| arg.to_owned(), String::from)
+}
+
+/// The local path an argument refers to, which URLs other than `file://` URIs
+/// do not have
+pub(crate) fn local_path(arg: &str) -> Option<PathBuf> {
+    match Url::parse(arg) {
+        Ok(url) if url.scheme() == "file" => url.to_file_path().ok(),
+        Ok(_) => None,
+        Err(_) => Some(PathBuf::from(arg)),
+    }
+}
+
+/// A path or URL as a local path, if it is a `file://` URI
+fn to_path(arg: &str) -> String {
+    match Url::parse(arg) {
+        Ok(url) if url.scheme() == "file" =>
+            local_path(arg).map_or_else(|| arg.to_owned(), |p| p.to_string_lossy().into_owned()),
+        _ => arg.to_owned(),
+    }
+}
+
+/// Expand the field codes inside a larger argument, joining files and URLs
+/// with spaces
+fn expand_inline(text: &str, entry: &DesktopEntry, args: &[String]) -> String {
+    use itertools::Itertools;
+
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('%') | None => out.push('%'),
+            Some('f' | 'F') => out.push_str(&args.iter().map(|a| to_path(a)).join(" ")),
+            Some('u' | 'U') => out.push_str(&args.iter().map(|a| to_url(a)).join(" ")),
+            Some('c') => out.push_str(&entry.name),
+            Some('k') => out.push_str(&entry.file_path.to_string_lossy()),
+            // `%i` expands to two arguments, which cannot be done inside one.
+            // The rest are deprecated and expand to nothing.
+            Some('i' | 'd' | 'D' | 'n' | 'N' | 'v' | 'm') => {},
+            Some(other) => {
+                out.push('%');
+                out.push(other);
+            },
+        }
+    }
+
+    out
+}
+
+/// The command line that runs `entry` with the given files or URLs. When the
+/// `Exec` line has no field code for them, they are appended at the end.
+pub(crate) fn expand(entry: &DesktopEntry, args: &[String]) -> Result<Vec<String>> {
+    let parts = split(&entry.exec)?;
+    let takes_files = parts
+        .iter()
+        .any(|p| field_codes(&p.text).any(|c| matches!(c, 'f' | 'F' | 'u' | 'U')));
+
+    let mut cmd = vec![];
+    for part in parts {
+        if !part.quoted {
+            match part.text.as_str() {
+                "%f" | "%F" => {
+                    cmd.extend(args.iter().map(|a| to_path(a)));
+                    continue;
+                },
+                "%u" | "%U" => {
+                    cmd.extend(args.iter().map(|a| to_url(a)));
+                    continue;
+                },
+                "%i" => {
+                    if let Some(icon) = &entry.icon {
+                        cmd.push("--icon".to_owned());
+                        cmd.push(icon.clone());
+                    }
+                    continue;
+                },
+                "%d" | "%D" | "%n" | "%N" | "%v" | "%m" => continue,
+                _ => {},
+            }
+        }
+
+        cmd.push(expand_inline(&part.text, entry, args));
+    }
+
+    if !takes_files {
+        cmd.extend_from_slice(args);
+    }
+
+    Ok(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    /// Exec lines as they appear in desktop files, the arguments they are run
+    /// with and the expected command line
+    const CORPUS: &[(&str, &[&str], &[&str])] = &[
+        ("firefox %u", &["/tmp/a b.html"], &[
+            "firefox",
+            "file:///tmp/a%20b.html",
+        ]),
+        ("firefox %u", &["https://example.com/?q=%20"], &[
+            "firefox",
+            "https://example.com/?q=%20",
+        ]),
+        (
+            "vlc --started-from-file %U",
+            &["/tmp/a.mkv", "/tmp/b.mkv"],
+            &[
+                "vlc",
+                "--started-from-file",
+                "file:///tmp/a.mkv",
+                "file:///tmp/b.mkv",
+            ],
+        ),
+        ("code --unity-launch %F", &["file:///tmp/a%20b.rs"], &[
+            "code",
+            "--unity-launch",
+            "/tmp/a b.rs",
+        ]),
+        (r#""/opt/Sublime Text/sublime_text" %F"#, &["/tmp/a"], &[
+            "/opt/Sublime Text/sublime_text",
+            "/tmp/a",
+        ]),
+        (
+            r#"sh -c "echo \\"\\$0\\" 100%%; exec foo \\\\ bar" %f"#,
+            &["/tmp/a"],
+            &["sh", "-c", r#"echo "$0" 100%; exec foo \ bar"#, "/tmp/a"],
+        ),
+        ("'/opt/My App/app' --url=%u", &["https://example.com"], &[
+            "/opt/My App/app",
+            "--url=https://example.com",
+        ]),
+        ("foo \"%F\"", &["/tmp/a", "/tmp/b"], &[
+            "foo",
+            "/tmp/a /tmp/b",
+        ]),
+        ("foo %i --name %c --from %k 50%% %d %D %n %N %v %m", &[], &[
+            "foo",
+            "--icon",
+            "foo-icon",
+            "--name",
+            "Foo",
+            "--from",
+            "/usr/share/applications/foo.desktop",
+            "50%",
+        ]),
+        ("steam steam://rungameid/570", &["/tmp/a"], &[
+            "steam",
+            "steam://rungameid/570",
+            "/tmp/a",
+        ]),
+        ("env\tFOO=1   bar\\sbaz %f", &["/tmp/a"], &[
+            "env", "FOO=1", "bar", "baz", "/tmp/a",
+        ]),
+        ("printf %s %f", &["/tmp/a"], &["printf", "%s", "/tmp/a"]),
+    ];
+
+    #[test]
+    fn exec_corpus() -> Result<()> {
+        for (exec, args, expected) in CORPUS {
+            let entry = DesktopEntry {
+                name: "Foo".into(),
+                icon: Some("foo-icon".into()),
+                exec: (*exec).to_owned(),
+                file_path: "/usr/share/applications/foo.desktop".into(),
+                ..DesktopEntry::default()
+            };
+            let args = args.iter().map(|a| (*a).to_owned()).collect::<Vec<_>>();
+
+            assert_eq!(expand(&entry, &args)?, *expected, "{exec}");
+        }
+
+        let cmus = DesktopEntry::try_from(PathBuf::from("tests/cmus.desktop"))?;
+        assert_eq!(expand(&cmus, &["/music/a b.mp3".into()])?, vec![
+            "bash",
+            "-c",
+            "(! pgrep cmus && tilix -e cmus && tilix -a session-add-down -e cava); sleep 0.1 && \
+             cmus-remote -q /music/a b.mp3",
+        ]);
+
+        assert!(takes_multiple("foo %U"));
+        assert!(!takes_multiple("foo %%U %u"));
+        split("foo \"bar").unwrap_err();
+        split("  ").unwrap_err();
+
+        Ok(())
+    }
+}
@@ -1,8 +1,9 @@
 use crate::{
-    common::{DesktopEntry, ExecMode},
+    common::{DesktopEntry, EntryIndex, ExecMode},
     Error, Result,
 };
-use std::{convert::TryFrom, ffi::OsString, fmt::Display, path::PathBuf, str::FromStr};
+use mime::Mime;
+use std::{ffi::OsString, fmt::Display, path::PathBuf, str::FromStr};
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub(crate) struct Handler(pub(crate) OsString);
@@ -27,27 +28,49 @@ impl Handler {
     }
 
     pub(crate) fn get_path(name: &std::ffi::OsStr) -> Option<PathBuf> {
-        let mut path = PathBuf::from("applications");
-        path.push(name);
-        xdg::BaseDirectories::new().ok()?.find_data_file(path)
+        EntryIndex::find(name)
     }
 
     pub(crate) fn resolve(name: OsString) -> Result<Self> {
         let path =
             Self::get_path(&name).ok_or_else(|| Error::NotFound(name.to_string_lossy().into()))?;
-        DesktopEntry::try_from(path)?;
+        EntryIndex::lookup(&path).ok_or(Error::BadEntry(path))?;
         Ok(Self(name))
     }
 
     pub(crate) fn get_entry(&self) -> Result<DesktopEntry> {
-        DesktopEntry::try_from(Self::get_path(&self.0).unwrap())
+        let path = Self::get_path(&self.0).ok_or_else(|| Error::NotFound(self.to_string()))?;
+        EntryIndex::lookup(&path).ok_or(Error::BadEntry(path))
     }
 
-    pub(crate) fn launch(&self, args: Vec<String>) -> Result<()> {
-        self.get_entry()?.exec(ExecMode::Launch, args)
+    /// Whether the handler can be used, which it cannot if its desktop file is
+    /// hidden or its `TryExec` binary is missing. Handlers whose desktop file
+    /// cannot be read are kept, so that launching them reports why.
+    pub(crate) fn is_usable(&self) -> bool {
+        self.get_entry().map_or(true, |e| e.is_usable())
     }
 
-    pub(crate) fn open(&self, args: Vec<String>) -> Result<()> {
-        self.get_entry()?.exec(ExecMode::Open, args)
+    /// The entry, narrowed down to one of its actions if given
+    pub(crate) fn get_entry_for(&self, action: Option<&str>) -> Result<DesktopEntry> {
+        let entry = self.get_entry()?;
+        match action {
+            Some(action) => entry.with_action(action),
+            None => Ok(entry),
+        }
+    }
+
+    pub(crate) fn launch(
+        &self,
+        mime: &Mime,
+        args: Vec<String>,
+        action: Option<&str>,
+    ) -> Result<()> {
+        self.get_entry_for(action)?
+            .exec(ExecMode::Launch, args, Some(mime))
+    }
+
+    pub(crate) fn open(&self, mime: &Mime, args: Vec<String>, action: Option<&str>) -> Result<()> {
+        self.get_entry_for(action)?
+            .exec(ExecMode::Open, args, Some(mime))
     }
 }
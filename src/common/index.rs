@@ -0,0 +1,433 @@
+use crate::{
+    common::{desktop_entry::desktop_id, DesktopEntry},
+    Result,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::TryFrom,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Bumped whenever the layout of the index or of `DesktopEntry` changes, so
+/// that an old index is rebuilt instead of misread
+const VERSION: u32 = 9;
+
+pub(crate) static ENTRIES: Lazy<EntryIndex> = Lazy::new(EntryIndex::load);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct File {
+    modified: Option<SystemTime>,
+    /// `None` if the desktop file could not be parsed
+    entry:    Option<DesktopEntry>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct Dir {
+    /// Of the directory and each of its subdirectories, by their path relative
+    /// to it
+    modified: BTreeMap<PathBuf, Option<SystemTime>>,
+    /// By their path relative to the directory
+    files:    BTreeMap<PathBuf, File>,
+}
+
+/// Parsed desktop entries of every `applications` directory, kept in
+/// `$XDG_CACHE_HOME/handlr/entries.bin` so they are only parsed again once
+/// they change
+#[derive(Debug, Default, Clone)]
+pub(crate) struct EntryIndex {
+    /// In order of precedence
+    dirs: Vec<(PathBuf, Dir)>,
+}
+
+impl EntryIndex {
+    pub(crate) fn path() -> Result<PathBuf> {
+        Ok(xdg::BaseDirectories::with_prefix("handlr")?.place_cache_file("entries.bin")?)
+    }
+
+    /// The `applications` directories desktop files are looked up in, in order
+    /// of precedence
+    pub(crate) fn dirs() -> Result<Vec<PathBuf>> {
+        use itertools::Itertools;
+
+        let dirs = xdg::BaseDirectories::new()?;
+        Ok(std::iter::once(dirs.get_data_home())
+            .chain(dirs.get_data_dirs())
+            .map(|d| d.join("applications"))
+            .unique()
+            .collect())
+    }
+
+    fn read() -> Option<Self> {
+        Self::read_from(&Self::path().ok()?)
+    }
+
+    fn read_from(path: &Path) -> Option<Self> {
+        let raw = std::fs::read(path).ok()?;
+        if bincode::deserialize::<u32>(&raw).ok()? != VERSION {
+            return None;
+        }
+
+        // Entries are localized while parsing, so they are only valid for the
+        // locale they were parsed in
+        let (_, locale, dirs) = bincode::deserialize::<(u32, Option<String>, _)>(&raw).ok()?;
+        (locale == super::desktop_entry::locale()).then(|| Self { dirs })
+    }
+
+    fn save(&self) -> Result<()> {
+        self.save_to(&Self::path()?)
+    }
+
+    fn save_to(&self, path: &Path) -> Result<()> {
+        crate::utils::write_atomic(
+            path,
+            &bincode::serialize(&(VERSION, super::desktop_entry::locale(), &self.dirs))?,
+        )
+    }
+
+    /// Read the index and bring it up to date. The index only saves time, so
+    /// failing to read or write it is not an error.
+    fn load() -> Self {
+        let mut index = Self::read().unwrap_or_default();
+        if index.refresh(Self::dirs().unwrap_or_default()) {
+            let _ = index.save();
+        }
+
+        index
+    }
+
+    /// Parse every desktop file from scratch
+    pub(crate) fn rebuild() -> Result<Self> {
+        let mut index = Self::default();
+        index.refresh(Self::dirs()?);
+        index.save()?;
+
+        Ok(index)
+    }
+
+    pub(crate) fn clear() -> Result<()> {
+        let path = Self::path()?;
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+
+        Ok(())
+    }
+
+    /// Parse the desktop files that were added or modified since the index was
+    /// written, returning whether there were any
+    pub(crate) fn refresh(&mut self, dirs: Vec<PathBuf>) -> bool {
+        let mut old = std::mem::take(&mut self.dirs)
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        let mut changed = false;
+
+        for path in dirs {
+            let old_dir = old.remove(&path);
+
+            // Unchanged directories still have the same files, though they may
+            // have been edited in place
+            let (dir_modified, names) = match &old_dir {
+                Some(dir)
+                    if dir
+                        .modified
+                        .iter()
+                        .all(|(sub, time)| modified(&path.join(sub)) == *time) =>
+                    (dir.modified.clone(), dir.files.keys().cloned().collect()),
+                _ => {
+                    changed = true;
+                    desktop_files(&path)
+                },
+            };
+            let mut old_files = old_dir.map(|dir| dir.files).unwrap_or_default();
+
+            let mut files = BTreeMap::new();
+            for name in names {
+                let file_path = path.join(&name);
+                let file_modified = modified(&file_path);
+
+                let file = match old_files.remove(&name) {
+                    Some(file) if file.modified == file_modified => file,
+                    _ => {
+                        changed = true;
+                        File {
+                            modified: file_modified,
+                            entry:    DesktopEntry::try_from(file_path).ok(),
+                        }
+                    },
+                };
+                files.insert(name, file);
+            }
+
+            self.dirs.push((path, Dir {
+                modified: dir_modified,
+                files,
+            }));
+        }
+
+        changed || !old.is_empty()
+    }
+
+    /// The entry of a desktop file, which is parsed if it is not indexed
+    pub(crate) fn get(&self, path: &Path) -> Option<DesktopEntry> {
+        let indexed = self
+            .dirs
+            .iter()
+            .find_map(|(dir, files)| files.files.get(path.strip_prefix(dir).ok()?));
+
+        match indexed {
+            Some(file) => file.entry.clone(),
+            None => DesktopEntry::try_from(path.to_owned()).ok(),
+        }
+    }
+
+    /// The entry of a single desktop file. It comes from the index if something
+    /// already needed that, but is not worth loading the whole index for.
+    pub(crate) fn lookup(path: &Path) -> Option<DesktopEntry> {
+        match Lazy::get(&ENTRIES) {
+            Some(index) => index.get(path),
+            None => DesktopEntry::try_from(path.to_owned()).ok(),
+        }
+    }
+
+    /// The desktop file with the given ID in the directory of highest
+    /// precedence that has one
+    pub(crate) fn find(id: &OsStr) -> Option<PathBuf> {
+        Self::find_all(id).next()
+    }
+
+    /// Every desktop file with the given ID, in order of precedence. Only the
+    /// first one is used, it shadows the others. Directories are only looked
+    /// into as the iterator gets to them.
+    pub(crate) fn find_all(id: &OsStr) -> impl Iterator<Item = PathBuf> + '_ {
+        Self::dirs()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(move |dir| match id.to_str() {
+                Some(id) => find_in(&dir, id),
+                None => Some(dir.join(id)).filter(|p| p.is_file()),
+            })
+    }
+
+    /// Every valid entry, skipping desktop files hidden by one with the same
+    /// ID in a directory of higher precedence
+    pub(crate) fn entries(&self) -> impl Iterator<Item = &DesktopEntry> {
+        let mut seen = HashSet::new();
+
+        self.dirs
+            .iter()
+            .flat_map(|(path, dir)| dir.files.iter().map(move |(name, file)| (path, name, file)))
+            .filter(move |(path, name, _)| {
+                desktop_id(&path.join(name)).is_some_and(|id| seen.insert(id))
+            })
+            .filter_map(|(_, _, file)| file.entry.as_ref())
+    }
+
+    pub(crate) fn print_stats(&self) -> Result<()> {
+        let path = Self::path()?;
+        println!("Index: {}", path.display());
+        match path.metadata() {
+            Ok(metadata) => println!(
+                "Size: {} bytes, updated {}",
+                metadata.len(),
+                metadata.modified().map_or_else(
+                    |_| "-".into(),
+                    |m| humantime::format_rfc3339_seconds(m).to_string()
+                )
+            ),
+            Err(_) => println!("Not written yet"),
+        }
+
+        let rows = self
+            .dirs
+            .iter()
+            .map(|(path, dir)| {
+                let valid = dir.files.values().filter(|f| f.entry.is_some()).count();
+                vec![
+                    path.display().to_string(),
+                    valid.to_string(),
+                    (dir.files.len() - valid).to_string(),
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        ascii_table::AsciiTable {
+            max_width: usize::MAX,
+            columns:   ["Directory", "Entries", "Invalid"]
+                .iter()
+                .map(|h| ascii_table::Column::with_header(h))
+                .enumerate()
+                .collect(),
+        }
+        .print(rows);
+
+        Ok(())
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    path.metadata().and_then(|m| m.modified()).ok()
+}
+
+/// The desktop files below a directory, along with the modification times of
+/// the directory and its subdirectories, all relative to it
+fn desktop_files(dir: &Path) -> (BTreeMap<PathBuf, Option<SystemTime>>, Vec<PathBuf>) {
+    let mut dirs = BTreeMap::new();
+    let mut files = vec![];
+    let mut pending = vec![PathBuf::new()];
+
+    while let Some(sub) = pending.pop() {
+        let path = dir.join(&sub);
+        dirs.insert(sub.clone(), modified(&path));
+
+        let Ok(entries) = std::fs::read_dir(&path) else {
+            continue;
+        };
+        // Symlinked directories are not followed, so they cannot form a loop
+        for entry in entries.filter_map(std::result::Result::ok) {
+            let name = sub.join(entry.file_name());
+            if entry.file_type().is_ok_and(|t| t.is_dir()) {
+                pending.push(name);
+            } else if name.extension().and_then(OsStr::to_str) == Some("desktop") {
+                files.push(name);
+            }
+        }
+    }
+
+    (dirs, files)
+}
+
+/// The desktop file with the given ID below `dir`. Each dash of the ID may
+/// stand for a subdirectory, so `kde4-okular.desktop` is also looked up as
+/// `kde4/okular.desktop`.
+fn find_in(dir: &Path, id: &str) -> Option<PathBuf> {
+    let path = dir.join(id);
+    if path.is_file() {
+        return Some(path);
+    }
+
+    id.match_indices('-').find_map(|(i, _)| {
+        let sub = dir.join(&id[..i]);
+        sub.is_dir().then(|| find_in(&sub, &id[i + 1..]))?
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refresh_changed_entries() -> Result<()> {
+        let data = tempfile::tempdir()?;
+        let apps = data.path().join("applications");
+        std::fs::create_dir(&apps)?;
+        std::fs::write(
+            apps.join("mpv.desktop"),
+            "[Desktop Entry]\nName=mpv\nExec=mpv %U\nMimeType=video/mp4;\n",
+        )?;
+        let dirs = vec![apps.clone(), data.path().join("missing")];
+
+        let mut index = EntryIndex::default();
+        assert!(index.refresh(dirs.clone()));
+        assert!(!index.refresh(dirs.clone()));
+        assert_eq!(
+            index.entries().map(|e| e.name.as_str()).collect::<Vec<_>>(),
+            vec!["mpv"]
+        );
+
+        // Round trip through the on-disk format
+        let cache = data.path().join("entries.bin");
+        index.save_to(&cache)?;
+        let decoded = EntryIndex::read_from(&cache).unwrap();
+        assert_eq!(decoded.dirs.len(), index.dirs.len());
+        assert_eq!(
+            decoded
+                .entries()
+                .map(|e| e.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["mpv"]
+        );
+
+        // An index of another version or locale is not used
+        std::fs::write(
+            &cache,
+            bincode::serialize(&(
+                VERSION - 1,
+                crate::common::desktop_entry::locale(),
+                &index.dirs,
+            ))?,
+        )?;
+        assert!(EntryIndex::read_from(&cache).is_none());
+        std::fs::write(
+            &cache,
+            bincode::serialize(&(VERSION, Some("xx_XX"), &index.dirs))?,
+        )?;
+        assert!(EntryIndex::read_from(&cache).is_none());
+
+        std::fs::write(
+            apps.join("mpv.desktop"),
+            "[Desktop Entry]\nName=mpv player\nExec=mpv %U\nMimeType=video/mp4;\n",
+        )?;
+        std::fs::File::options()
+            .write(true)
+            .open(apps.join("mpv.desktop"))?
+            .set_modified(SystemTime::now() + std::time::Duration::from_secs(100))?;
+        assert!(index.refresh(dirs));
+        assert_eq!(
+            index.get(&apps.join("mpv.desktop")).map(|e| e.name),
+            Some("mpv player".into())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn nested_desktop_ids() -> Result<()> {
+        let data = tempfile::tempdir()?;
+        let (high, low) = (
+            data.path().join("high/applications"),
+            data.path().join("low/applications"),
+        );
+        std::fs::create_dir_all(high.join("kde4"))?;
+        std::fs::create_dir_all(low.join("kde4"))?;
+        let entry = |name| format!("[Desktop Entry]\nName={name}\nExec=okular %U\n");
+        std::fs::write(high.join("kde4-okular.desktop"), entry("high"))?;
+        std::fs::write(low.join("kde4/okular.desktop"), entry("low"))?;
+        std::fs::write(low.join("kde4/kate.desktop"), entry("kate"))?;
+
+        let mut index = EntryIndex::default();
+        index.refresh(vec![high.clone(), low.clone()]);
+        assert_eq!(
+            index
+                .entries()
+                .map(|e| (e.file_name.to_str().unwrap(), e.name.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("kde4-okular.desktop", "high"),
+                ("kde4-kate.desktop", "kate")
+            ]
+        );
+
+        assert_eq!(
+            find_in(&low, "kde4-kate.desktop"),
+            Some(low.join("kde4/kate.desktop"))
+        );
+        assert_eq!(
+            find_in(&high, "kde4-okular.desktop"),
+            Some(high.join("kde4-okular.desktop"))
+        );
+        assert_eq!(find_in(&low, "kde4-missing.desktop"), None);
+
+        // A file added to a subdirectory is picked up
+        std::fs::write(low.join("kde4/dolphin.desktop"), entry("dolphin"))?;
+        std::fs::File::open(low.join("kde4"))?
+            .set_modified(SystemTime::now() + std::time::Duration::from_secs(100))?;
+        assert!(index.refresh(vec![high, low]));
+        assert_eq!(index.entries().count(), 3);
+
+        Ok(())
+    }
+}
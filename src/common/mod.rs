@@ -1,11 +1,19 @@
+mod create;
 mod db;
 mod desktop_entry;
+mod exec;
 mod handler;
+mod index;
 mod mime_types;
 mod path;
+mod startup;
+mod terminal;
 
 pub(crate) use self::db::autocomplete as db_autocomplete;
-pub(crate) use desktop_entry::{DesktopEntry, Mode as ExecMode};
+pub(crate) use create::NewEntry;
+pub(crate) use desktop_entry::{DesktopEntry, Invocation, Mode as ExecMode};
 pub(crate) use handler::Handler;
+pub(crate) use index::{EntryIndex, ENTRIES};
 pub(crate) use mime_types::{MimeOrExtension, MimeType};
 pub(crate) use path::UserPath;
+pub(crate) use terminal::preferred as preferred_terminal;
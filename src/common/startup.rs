@@ -0,0 +1,292 @@
+//! Startup notification for entries with `StartupNotify=true`, so the window
+//! they open is focused instead of being held back by focus stealing
+//! prevention. See the [startup notification spec] and the [activation
+//! protocol] of Wayland.
+//!
+//! [startup notification spec]: https://specifications.freedesktop.org/startup-notification-spec/latest/
+//! [activation protocol]: https://wayland.app/protocols/xdg-activation-v1
+
+use crate::common::DesktopEntry;
+use std::{
+    process::Command,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+const DESKTOP_STARTUP_ID: &str = "DESKTOP_STARTUP_ID";
+const XDG_ACTIVATION_TOKEN: &str = "XDG_ACTIVATION_TOKEN";
+
+/// Tells apart the startup IDs of several apps launched at once
+static LAUNCHES: AtomicU32 = AtomicU32::new(0);
+
+/// The token handlr itself was started with, which is only valid once. It is
+/// removed from the environment so that no other app picks it up.
+fn take_given_token() -> Option<String> {
+    let token = [XDG_ACTIVATION_TOKEN, DESKTOP_STARTUP_ID]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|t| !t.is_empty()));
+
+    std::env::remove_var(XDG_ACTIVATION_TOKEN);
+    std::env::remove_var(DESKTOP_STARTUP_ID);
+
+    token
+}
+
+/// Hand `cmd` a token to activate its window with, if the entry supports
+/// startup notification. A token handlr was given is passed on, otherwise one
+/// is generated on X11. Wayland compositors only hand out tokens to clients
+/// with a surface, which handlr does not have.
+///
+/// Apps without startup notification never see a token, as they would not
+/// end the startup sequence. Returns the startup ID generated on X11, which
+/// has to be [`cancel`]led if the app fails to start.
+pub(crate) fn prepare(cmd: &mut Command, entry: &DesktopEntry, bin: &str) -> Option<String> {
+    cmd.env_remove(XDG_ACTIVATION_TOKEN)
+        .env_remove(DESKTOP_STARTUP_ID);
+    if !entry.startup_notify {
+        return None;
+    }
+
+    if let Some(token) = take_given_token() {
+        cmd.env(DESKTOP_STARTUP_ID, &token)
+            .env(XDG_ACTIVATION_TOKEN, &token);
+        None
+    } else if std::env::var_os("WAYLAND_DISPLAY").is_none() && std::env::var_os("DISPLAY").is_some()
+    {
+        // Failing to announce the startup only costs the focus of the window
+        let id = x11::initiate(entry, bin).ok()?;
+        cmd.env(DESKTOP_STARTUP_ID, &id);
+        Some(id)
+    } else {
+        None
+    }
+}
+
+/// End the startup sequence of an app that failed to start, so that the
+/// desktop stops showing it as starting
+pub(crate) fn cancel(id: &str) {
+    // Otherwise the sequence only ends when it times out
+    let _ = x11::broadcast(&remove_message(id));
+}
+
+/// A startup ID that is unique across hosts and launches, carrying the
+/// timestamp of the launch for focus stealing prevention
+fn startup_id(time: u32) -> String {
+    let host = std::fs::read_to_string("/proc/sys/kernel/hostname").unwrap_or_default();
+
+    format!(
+        "handlr-{}-{}-{}_TIME{time}",
+        std::process::id(),
+        LAUNCHES.fetch_add(1, Ordering::Relaxed),
+        host.trim()
+    )
+}
+
+/// A value of a message, quoted and escaped
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The `new:` message announcing that `entry` is starting
+fn new_message(id: &str, screen: usize, entry: &DesktopEntry, bin: &str) -> String {
+    use itertools::Itertools;
+
+    let description = format!("Launching {}", entry.name);
+    let application_id = entry.file_path.to_string_lossy();
+    let screen = screen.to_string();
+
+    let mut fields = vec![
+        ("ID", id),
+        ("NAME", entry.name.as_str()),
+        ("SCREEN", &screen),
+        ("BIN", bin),
+        ("DESCRIPTION", &description),
+        ("APPLICATION_ID", &application_id),
+    ];
+    if let Some(icon) = &entry.icon {
+        fields.push(("ICON", icon));
+    }
+    if let Some(class) = &entry.startup_wm_class {
+        fields.push(("WMCLASS", class));
+    }
+
+    format!(
+        "new: {}",
+        fields
+            .iter()
+            .map(|(key, value)| format!("{key}={}", quote(value)))
+            .join(" ")
+    )
+}
+
+/// The `remove:` message ending the startup sequence with the given ID
+fn remove_message(id: &str) -> String {
+    format!("remove: ID={}", quote(id))
+}
+
+/// A message split up into the 20 bytes a client message can carry, ending
+/// with a nul byte
+fn chunks(msg: &str) -> Vec<[u8; 20]> {
+    msg.bytes()
+        .chain(std::iter::once(0))
+        .collect::<Vec<_>>()
+        .chunks(20)
+        .map(|chunk| {
+            let mut data = [0; 20];
+            data[..chunk.len()].copy_from_slice(chunk);
+            data
+        })
+        .collect()
+}
+
+mod x11 {
+    use super::{chunks, new_message, startup_id};
+    use crate::common::DesktopEntry;
+    use x11rb::{
+        connection::Connection,
+        protocol::{
+            xproto::{
+                AtomEnum, ClientMessageEvent, ConnectionExt as _, CreateWindowAux, EventMask,
+                PropMode, Window, WindowClass,
+            },
+            Event,
+        },
+        rust_connection::RustConnection,
+        wrapper::ConnectionExt as _,
+        COPY_DEPTH_FROM_PARENT, COPY_FROM_PARENT,
+    };
+
+    type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+    /// A connection and a window to send messages from, as the spec wants
+    /// them to come from a window of the launcher
+    struct Sender {
+        conn:       RustConnection,
+        screen_num: usize,
+        window:     Window,
+    }
+
+    impl Sender {
+        fn new() -> Result<Self> {
+            let (conn, screen_num) = x11rb::connect(None)?;
+            let root = conn.setup().roots[screen_num].root;
+
+            let window = conn.generate_id()?;
+            conn.create_window(
+                COPY_DEPTH_FROM_PARENT,
+                window,
+                root,
+                -100,
+                -100,
+                1,
+                1,
+                0,
+                WindowClass::INPUT_OUTPUT,
+                COPY_FROM_PARENT,
+                &CreateWindowAux::new()
+                    .override_redirect(1)
+                    .event_mask(EventMask::PROPERTY_CHANGE),
+            )?;
+
+            Ok(Self {
+                conn,
+                screen_num,
+                window,
+            })
+        }
+
+        /// Send `msg` to every client and close the window
+        fn send(self, msg: &str) -> Result<()> {
+            let root = self.conn.setup().roots[self.screen_num].root;
+            let begin = self.conn.intern_atom(false, b"_NET_STARTUP_INFO_BEGIN")?;
+            let info = self.conn.intern_atom(false, b"_NET_STARTUP_INFO")?;
+            let (begin, info) = (begin.reply()?.atom, info.reply()?.atom);
+
+            for (i, data) in chunks(msg).into_iter().enumerate() {
+                let event = ClientMessageEvent::new(
+                    8,
+                    self.window,
+                    if i == 0 { begin } else { info },
+                    data,
+                );
+                self.conn
+                    .send_event(false, root, EventMask::PROPERTY_CHANGE, event)?;
+            }
+
+            self.conn.destroy_window(self.window)?;
+            self.conn.flush()?;
+
+            Ok(())
+        }
+    }
+
+    /// Broadcast that `entry` is starting and return its startup ID
+    pub(super) fn initiate(entry: &DesktopEntry, bin: &str) -> Result<String> {
+        let sender = Sender::new()?;
+
+        // The server time is only known from events, so touch a property to
+        // get one
+        sender.conn.change_property8(
+            PropMode::APPEND,
+            sender.window,
+            AtomEnum::WM_NAME,
+            AtomEnum::STRING,
+            &[],
+        )?;
+        sender.conn.flush()?;
+        let time = loop {
+            if let Event::PropertyNotify(event) = sender.conn.wait_for_event()? {
+                break event.time;
+            }
+        };
+
+        let id = startup_id(time);
+        let msg = new_message(&id, sender.screen_num, entry, bin);
+        sender.send(&msg)?;
+
+        Ok(id)
+    }
+
+    /// Broadcast a message that needs no timestamp
+    pub(super) fn broadcast(msg: &str) -> Result<()> {
+        Sender::new()?.send(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Result;
+    use std::{convert::TryFrom, path::PathBuf};
+
+    #[test]
+    fn new_message_chunks() -> Result<()> {
+        let entry = DesktopEntry::try_from(PathBuf::from("tests/firefox.desktop"))?;
+        assert!(entry.startup_notify);
+        assert_eq!(entry.startup_wm_class.as_deref(), Some("firefox"));
+
+        let msg = new_message("handlr-1-0-host_TIME42", 0, &entry, "firefox");
+        assert_eq!(
+            msg,
+            "new: ID=\"handlr-1-0-host_TIME42\" NAME=\"Firefox\" SCREEN=\"0\" BIN=\"firefox\" \
+             DESCRIPTION=\"Launching Firefox\" APPLICATION_ID=\"tests/firefox.desktop\" \
+             WMCLASS=\"firefox\""
+        );
+
+        let data = chunks(&msg);
+        assert_eq!(data.len(), msg.len() / 20 + 1);
+        assert_eq!(&data[0], b"new: ID=\"handlr-1-0-");
+        let joined = data.concat();
+        assert_eq!(&joined[..msg.len()], msg.as_bytes());
+        assert_eq!(joined[msg.len()], 0);
+
+        assert_eq!(
+            remove_message("handlr-1-0-host_TIME42"),
+            "remove: ID=\"handlr-1-0-host_TIME42\""
+        );
+
+        // A message filling the last chunk needs another one for the nul byte
+        assert_eq!(chunks(&"a".repeat(20)).len(), 2);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,199 @@
+//! Picking and running the terminal emulator for `Terminal=true` entries, as
+//! proposed by the [xdg-terminal-exec spec]
+//!
+//! [xdg-terminal-exec spec]: https://gitlab.freedesktop.org/terminal-wg/specifications/-/merge_requests/3
+
+use crate::{
+    common::{exec, DesktopEntry, EntryIndex, Handler},
+    utils, Result,
+};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// How a terminal emulator takes the command to run and its other options,
+/// from the `X-TerminalArg*` keys of its desktop entry. Options ending in `=`
+/// are joined with their value, others are passed as a separate argument.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct TerminalArgs {
+    /// Precedes the command, `-e` if unset and nothing if empty
+    pub(crate) exec:   Option<String>,
+    pub(crate) app_id: Option<String>,
+    pub(crate) title:  Option<String>,
+    pub(crate) dir:    Option<String>,
+    /// Keeps the terminal open after the command exits
+    pub(crate) hold:   Option<String>,
+}
+
+/// The `*xdg-terminals.list` files listing the preferred terminals, in order
+/// of precedence
+fn lists() -> Result<Vec<PathBuf>> {
+    let dirs = xdg::BaseDirectories::new()?;
+    let names = utils::current_desktops()
+        .iter()
+        .map(|desktop| format!("{}-xdg-terminals.list", desktop.to_lowercase()))
+        .chain(std::iter::once("xdg-terminals.list".to_owned()))
+        .collect::<Vec<_>>();
+
+    let data_dirs = std::iter::once(dirs.get_data_home())
+        .chain(dirs.get_data_dirs())
+        .map(|d| d.join("xdg-terminal-exec"));
+
+    Ok(std::iter::once(dirs.get_config_home())
+        .chain(dirs.get_config_dirs())
+        .chain(data_dirs)
+        .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+        .collect())
+}
+
+/// The desktop file IDs in a terminal list, each with an optional action
+fn parse_list(contents: &str) -> Vec<(&str, Option<&str>)> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.split_once(':') {
+            Some((id, action)) => (id, Some(action)),
+            None => (line, None),
+        })
+        .collect()
+}
+
+/// The first usable terminal from the `*xdg-terminals.list` files
+pub(crate) fn preferred() -> Option<DesktopEntry> {
+    lists()
+        .ok()?
+        .iter()
+        .filter_map(|list| std::fs::read_to_string(list).ok())
+        .find_map(|contents| {
+            parse_list(&contents).into_iter().find_map(|(id, action)| {
+                let entry = EntryIndex::lookup(&Handler::get_path(id.as_ref())?)?;
+                if !entry.is_usable() {
+                    return None;
+                }
+                match action {
+                    Some(action) => entry.with_action(action).ok(),
+                    None => Some(entry),
+                }
+            })
+        })
+}
+
+/// The command line that runs `command` for `entry` in the given terminal,
+/// keeping it open after the command exits if `hold` is set and the terminal
+/// supports it
+pub(crate) fn command(
+    terminal: &DesktopEntry,
+    entry: &DesktopEntry,
+    dir: Option<&Path>,
+    command: Vec<String>,
+    hold: bool,
+) -> Result<Vec<String>> {
+    let args = &terminal.terminal_args;
+    let mut cmd = exec::expand(terminal, &[])?;
+
+    let mut option = |option: &Option<String>, value: &str| match option.as_deref() {
+        Some(option) if option.ends_with('=') => cmd.push(format!("{option}{value}")),
+        Some(option) => cmd.extend([option.to_owned(), value.to_owned()]),
+        None => {},
+    };
+    let app_id = entry.file_name.to_string_lossy();
+    option(&args.app_id, app_id.trim_end_matches(".desktop"));
+    option(&args.title, &entry.name);
+    if let Some(dir) = dir {
+        option(&args.dir, &dir.to_string_lossy());
+    }
+    if let Some(flag) = args.hold.as_deref().filter(|_| hold) {
+        cmd.extend(shlex::split(flag).unwrap_or_default());
+    }
+
+    match args.exec.as_deref() {
+        None => cmd.push("-e".to_owned()),
+        Some("") => {},
+        Some(exec) => cmd.extend(shlex::split(exec).unwrap_or_default()),
+    }
+    cmd.extend(command);
+
+    Ok(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn terminal_command() -> Result<()> {
+        assert_eq!(
+            parse_list("# preferred\nfoot.desktop:server\n\n  kitty.desktop \n"),
+            vec![("foot.desktop", Some("server")), ("kitty.desktop", None)]
+        );
+
+        let kgx = DesktopEntry::try_from(PathBuf::from("tests/kgx.desktop"))?;
+        let htop = DesktopEntry {
+            name: "Htop".into(),
+            file_name: "htop.desktop".into(),
+            ..DesktopEntry::default()
+        };
+        assert_eq!(
+            command(
+                &kgx,
+                &htop,
+                Some(Path::new("/tmp")),
+                vec!["htop".into()],
+                true
+            )?,
+            vec![
+                "kgx",
+                "--title=Htop",
+                "--working-directory=/tmp",
+                "--",
+                "htop"
+            ]
+        );
+
+        let wezterm = DesktopEntry {
+            exec: "wezterm".into(),
+            terminal_args: TerminalArgs {
+                exec: Some("start --".into()),
+                app_id: Some("--class".into()),
+                ..TerminalArgs::default()
+            },
+            ..DesktopEntry::default()
+        };
+        assert_eq!(
+            command(&wezterm, &htop, None, vec!["htop".into()], false)?,
+            vec!["wezterm", "--class", "htop", "start", "--", "htop"]
+        );
+
+        // The terminal is only held open when asked to
+        let foot = DesktopEntry {
+            exec: "foot".into(),
+            terminal_args: TerminalArgs {
+                exec: Some("--".into()),
+                hold: Some("--hold".into()),
+                ..TerminalArgs::default()
+            },
+            ..DesktopEntry::default()
+        };
+        assert_eq!(
+            command(&foot, &htop, None, vec!["htop".into()], true)?,
+            vec!["foot", "--hold", "--", "htop"]
+        );
+        assert_eq!(
+            command(&foot, &htop, None, vec!["htop".into()], false)?,
+            vec!["foot", "--", "htop"]
+        );
+
+        // Terminals without the keys get the `-e` of old
+        let xterm = DesktopEntry {
+            exec: "xterm".into(),
+            ..DesktopEntry::default()
+        };
+        assert_eq!(
+            command(&xterm, &htop, None, vec!["htop".into()], true)?,
+            vec!["xterm", "-e", "htop"]
+        );
+
+        Ok(())
+    }
+}
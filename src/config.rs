@@ -1,39 +1,91 @@
-use crate::{apps::SystemApps, common::Handler, Error, Result};
+use crate::{
+    apps::SystemApps,
+    common::{DesktopEntry, Handler},
+    Error, Result,
+};
 use mime::Mime;
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
-use std::str::FromStr;
+use std::{collections::HashMap, str::FromStr};
 
 pub(crate) static CONFIG: Lazy<Config> = Lazy::new(Config::load);
 
 #[derive(Serialize, Deserialize)]
 #[serde(default)]
 pub(crate) struct Config {
-    pub(crate) enable_selector: bool,
-    pub(crate) selector:        String,
+    pub(crate) enable_selector:        bool,
+    pub(crate) selector:               String,
+    /// Working directory of launched apps, unless their desktop entry sets
+    /// `Path=`
+    pub(crate) launch_cwd:             LaunchCwd,
+    /// Command launched apps are run under, like `systemd-run --user --scope
+    /// --unit=app-{app_id}`. `{app_id}` is replaced by the desktop file ID
+    /// without `.desktop`, `{name}` by the name of the app.
+    pub(crate) launch_wrapper:         Option<String>,
+    /// Keep the output of launched apps in
+    /// `$XDG_STATE_HOME/handlr/logs/<desktop file ID>.log`
+    pub(crate) launch_logs:            bool,
+    /// Keep the terminal of `Terminal=true` entries open after they exit, if
+    /// it has an `X-TerminalArgHold` key
+    pub(crate) hold_terminal:          bool,
+    /// Per-handler overrides of `launch_cwd` that also take precedence over
+    /// `Path=`, keyed by desktop file name
+    pub(crate) handler_launch_cwd:     HashMap<String, LaunchCwd>,
+    /// Per-handler overrides of `launch_wrapper`, keyed by desktop file name.
+    /// An empty command runs the handler as is.
+    pub(crate) handler_launch_wrapper: HashMap<String, String>,
+    /// Per-mime overrides of `launch_wrapper`, which can be wildcards like
+    /// `video/*`. Per-handler ones take precedence.
+    pub(crate) mime_launch_wrapper:    HashMap<String, String>,
+}
+
+/// Where launched apps run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum LaunchCwd {
+    /// The working directory of handlr itself
+    Inherit,
+    /// The directory of the first opened file, or the opened directory
+    FileDir,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
-            enable_selector: false,
-            selector:        "rofi -dmenu -i -p 'Open With: '".into(),
+            enable_selector:        false,
+            selector:               "rofi -dmenu -i -p 'Open With: '".into(),
+            launch_cwd:             LaunchCwd::Inherit,
+            launch_wrapper:         None,
+            launch_logs:            false,
+            hold_terminal:          false,
+            handler_launch_cwd:     HashMap::new(),
+            handler_launch_wrapper: HashMap::new(),
+            mime_launch_wrapper:    HashMap::new(),
         }
     }
 }
 
 impl Config {
-    pub(crate) fn terminal() -> Result<String> {
-        let terminal_entry = crate::apps::APPS
-            .get_handler(&Mime::from_str("x-scheme-handler/terminal").unwrap())
-            .ok()
-            .and_then(|h| h.get_entry().ok());
-
-        terminal_entry
+    /// The terminal emulator to run `Terminal=true` entries in. The
+    /// `*xdg-terminals.list` files of the xdg-terminal-exec spec come first,
+    /// then the `x-scheme-handler/terminal` handler, which is guessed if
+    /// there is none. The guess is only set and announced if `persist` is.
+    pub(crate) fn terminal(persist: bool) -> Result<DesktopEntry> {
+        crate::common::preferred_terminal()
             .or_else(|| {
-                let entry = SystemApps::get_entries()
+                crate::apps::APPS
+                    .get_handler(&Mime::from_str("x-scheme-handler/terminal").unwrap())
                     .ok()?
-                    .find(|(_handler, entry)| entry.categories.contains("TerminalEmulator"))?;
+                    .get_entry()
+                    .ok()
+            })
+            .or_else(|| {
+                let entry = SystemApps::get_entries().find(|(_handler, entry)| {
+                    entry.is_usable() && entry.categories.contains("TerminalEmulator")
+                })?;
+                if !persist {
+                    return Some(entry.1);
+                }
 
                 crate::utils::notify(
                     "handlr",
@@ -45,7 +97,8 @@ impl Config {
                 )
                 .ok()?;
 
-                let mut apps = (*crate::apps::APPS).clone();
+                let _lock = crate::apps::MimeApps::lock().ok()?;
+                let mut apps = crate::apps::MimeApps::read().ok()?;
                 apps.set_handler(
                     Mime::from_str("x-scheme-handler/terminal").unwrap(),
                     Handler::assume_valid(entry.0),
@@ -54,10 +107,26 @@ impl Config {
 
                 Some(entry.1)
             })
-            .map(|e| e.exec)
             .ok_or(Error::NoTerminal)
     }
 
+    /// The wrapper command template for a handler opening `mime`, if any
+    pub(crate) fn launch_wrapper(&self, handler: &str, mime: Option<&Mime>) -> Option<&str> {
+        let by_mime = || {
+            let mime = mime?;
+            self.mime_launch_wrapper
+                .get(mime.essence_str())
+                .or_else(|| self.mime_launch_wrapper.get(&format!("{}/*", mime.type_())))
+        };
+
+        self.handler_launch_wrapper
+            .get(handler)
+            .or_else(by_mime)
+            .or(self.launch_wrapper.as_ref())
+            .map(String::as_str)
+            .filter(|wrapper| !wrapper.trim().is_empty())
+    }
+
     pub(crate) fn load() -> Self {
         confy::load("handlr").unwrap()
     }
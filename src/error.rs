@@ -1,8 +1,5 @@
 #[derive(Debug, thiserror::Error)]
 pub(crate) enum Error {
-    #[error(transparent)]
-    ParseApps(#[from] pest::error::Error<crate::apps::MimeappsRule>),
-
     #[error(transparent)]
     Io(#[from] std::io::Error),
 
@@ -12,6 +9,18 @@ pub(crate) enum Error {
     #[error(transparent)]
     Config(#[from] confy::ConfyError),
 
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Bincode(#[from] bincode::Error),
+
+    #[error(transparent)]
+    TomlDe(#[from] toml::de::Error),
+
+    #[error(transparent)]
+    TomlSer(#[from] toml::ser::Error),
+
     #[error("no handlers found for '{0}'")]
     NotFound(String),
 
@@ -27,6 +36,18 @@ pub(crate) enum Error {
     #[error("malformed desktop entry at {0}")]
     BadEntry(std::path::PathBuf),
 
+    #[error("{0} already exists, pass --force to replace it")]
+    EntryExists(std::path::PathBuf),
+
+    #[error("malformed Exec line: {0}")]
+    BadExec(String),
+
+    #[error("malformed launch wrapper: {0}")]
+    BadWrapper(String),
+
+    #[error("{0} has no action '{1}'")]
+    UnknownAction(String, String),
+
     #[error("error spawning selector process '{0}'")]
     Selector(String),
 
@@ -38,6 +59,12 @@ pub(crate) enum Error {
 
     #[error("Bad path: {0}")]
     BadPath(String),
+
+    #[error("no change with id {0} in the history")]
+    UnknownChange(u64),
+
+    #[error("{0} is not supported")]
+    Unsupported(&'static str),
 }
 
 pub(crate) type Result<T, E = Error> = std::result::Result<T, E>;
@@ -68,23 +68,43 @@ mod utils;
 fn main() -> Result<()> {
     use cli::Cmd;
     use common::Handler;
-    use std::collections::HashMap;
+    use std::collections::BTreeMap;
 
     // create config if it doesn't exist
     Lazy::force(&CONFIG);
 
-    let mut apps = (*apps::APPS).clone();
-
     let res = || -> Result<()> {
-        match Cmd::parse() {
+        let cmd = Cmd::parse();
+
+        // Hold the lock from reading `mimeapps.list` until it is written back
+        let _lock = if cmd.modifies_mimeapps() {
+            Some(apps::MimeApps::lock()?)
+        } else {
+            None
+        };
+        let mut apps = (*apps::APPS).clone();
+
+        match cmd {
             Cmd::Ask {
                 path,
                 skim,
                 plain,
                 config,
+                include_hidden,
+                dry_run,
+                json,
             } => {
-                let selected = apps.ask_handler(&path.get_mime()?.0, skim, plain, config)?;
-                selected.exec(common::ExecMode::Open, vec![path.to_string()])?;
+                let mime = path.get_mime()?.0;
+                let selected = apps.ask_handler(&mime, skim, plain, config, include_hidden)?;
+                let args = vec![path.to_string()];
+
+                if dry_run {
+                    let invocations =
+                        selected.invocations(common::ExecMode::Open, args, Some(&mime), true)?;
+                    common::Invocation::print_all(&invocations, json)?;
+                } else {
+                    selected.exec(common::ExecMode::Open, args, Some(&mime))?;
+                }
             },
             Cmd::Set { mime, handler } => {
                 apps.set_handler(mime.0, handler);
@@ -94,38 +114,188 @@ fn main() -> Result<()> {
                 apps.add_handler(mime.0, handler);
                 apps.save()?;
             },
-            Cmd::Launch { mime, args } => {
-                apps.get_handler(&mime.0)?
-                    .launch(args.into_iter().map(|a| a.to_string()).collect())?;
+            Cmd::RemoveAssociation { mime, handler } => {
+                apps.remove_association(mime.0, Handler::assume_valid(handler.into()));
+                apps.save()?;
+            },
+            Cmd::History => {
+                apps::History::read()?.print();
+            },
+            Cmd::Undo { count } => {
+                apps.undo(count)?;
+            },
+            Cmd::RevertTo { id } => {
+                apps.revert_to(id)?;
+            },
+            Cmd::Export { format } => {
+                print!("{}", apps.export().serialize(format)?);
+            },
+            Cmd::Import { file, format } => {
+                use colored::Colorize;
+
+                let format = format.unwrap_or_else(|| apps::ProfileFormat::from_path(&file));
+                for skipped in apps.import(apps::Profile::read(&file, format)?) {
+                    eprintln!("[{}]: {}", "skipped".yellow().bold(), skipped);
+                }
+                apps.save()?;
+            },
+            Cmd::Apply { file, check } => {
+                let file = match file {
+                    Some(file) => file,
+                    None => xdg::BaseDirectories::with_prefix("handlr")?
+                        .get_config_file("associations.toml"),
+                };
+                let desired = apps::Profile::read(&file, apps::ProfileFormat::from_path(&file))?;
+                let plan = apps.plan(&desired)?;
+
+                if plan.is_empty() {
+                    println!("No changes, mimeapps.list matches {}", file.display());
+                    return Ok(());
+                }
+
+                apps::MimeApps::print_plan(&plan);
+
+                if check {
+                    std::process::exit(1);
+                }
+                apps.apply(&plan)?;
+            },
+            Cmd::Lint { fix, json } => {
+                let lints = apps.lint();
+
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&lints)?);
+                } else {
+                    for lint in &lints {
+                        println!("{lint}");
+                    }
+                }
+
+                if fix {
+                    apps.fix(&lints)?;
+                }
+
+                if lints.iter().any(|l| !(fix && l.fixable)) {
+                    std::process::exit(1);
+                }
+            },
+            Cmd::Launch {
+                mime,
+                args,
+                action,
+                dry_run,
+                json,
+            } => {
+                let handler = apps.get_handler(&mime.0)?;
+                let args = args.into_iter().map(|a| a.to_string()).collect();
+
+                if dry_run {
+                    let invocations = handler.get_entry_for(action.as_deref())?.invocations(
+                        common::ExecMode::Launch,
+                        args,
+                        Some(&mime.0),
+                        true,
+                    )?;
+                    common::Invocation::print_all(&invocations, json)?;
+                } else {
+                    handler.launch(&mime.0, args, action.as_deref())?;
+                }
             },
             Cmd::Get { mime, json } => {
                 apps.show_handler(&mime.0, json)?;
             },
-            Cmd::Open { paths } => {
-                let mut handlers: HashMap<Handler, Vec<String>> = HashMap::new();
+            Cmd::Open {
+                paths,
+                action,
+                dry_run,
+                json,
+            } => {
+                // Files opened by the same handler under the same launch
+                // wrapper are opened together, as the type of the first one
+                let mut handlers = BTreeMap::new();
 
                 for path in paths {
+                    let mime = path.get_mime()?.0;
+                    let handler = apps.get_handler(&mime)?;
+                    let wrapper = CONFIG.launch_wrapper(&handler.to_string(), Some(&mime));
                     handlers
-                        .entry(apps.get_handler(&path.get_mime()?.0)?)
-                        .or_default()
+                        .entry((handler, wrapper))
+                        .or_insert_with(|| (mime, vec![]))
+                        .1
                         .push(path.to_string());
                 }
 
-                for (handler, paths) in handlers {
-                    handler.open(paths)?;
+                if dry_run {
+                    let mut invocations = vec![];
+                    for ((handler, _), (mime, paths)) in handlers {
+                        invocations.extend(handler.get_entry_for(action.as_deref())?.invocations(
+                            common::ExecMode::Open,
+                            paths,
+                            Some(&mime),
+                            true,
+                        )?);
+                    }
+                    common::Invocation::print_all(&invocations, json)?;
+                } else {
+                    for ((handler, _), (mime, paths)) in handlers {
+                        handler.open(&mime, paths, action.as_deref())?;
+                    }
                 }
             },
-            Cmd::List { all } => {
-                apps.print(all);
+            Cmd::List {
+                all,
+                include_hidden,
+            } => {
+                apps.print(all, include_hidden);
             },
             Cmd::Unset { mime } => {
                 apps.remove_handler(&mime.0)?;
             },
+            Cmd::Create {
+                id,
+                exec,
+                app_name,
+                terminal,
+                mimes,
+                set_default,
+                interactive,
+                force,
+            } => {
+                let mut new = common::NewEntry {
+                    id,
+                    name: app_name,
+                    exec,
+                    terminal,
+                    mimes: mimes.into_iter().map(|m| m.0).collect(),
+                    set_default,
+                };
+                if interactive {
+                    new.ask()?;
+                }
+
+                let entry = new.write(force)?;
+                println!("Created {}", entry.file_path.display());
+
+                if new.set_default && !entry.mimes.is_empty() {
+                    for mime in entry.mimes {
+                        apps.set_handler(mime, Handler::assume_valid(entry.file_name.clone()));
+                    }
+                    apps.save()?;
+                }
+            },
+            Cmd::Cache { action } => match action {
+                cli::CacheCmd::Rebuild => {
+                    let index = common::EntryIndex::rebuild()?;
+                    println!("Indexed {} desktop entries", index.entries().count());
+                },
+                cli::CacheCmd::Clear => common::EntryIndex::clear()?,
+                cli::CacheCmd::Stats => common::ENTRIES.print_stats()?,
+            },
             Cmd::Edit { handler } => {
                 apps.edit_handler(&handler)?;
             },
-            Cmd::Cat { handler } => {
-                apps.cat_handler(&handler)?;
+            Cmd::Cat { handler, effective } => {
+                apps.cat_handler(&handler, effective)?;
             },
             Cmd::Status { handler } => {
                 apps.get_status(&handler)?;
@@ -1,4 +1,5 @@
-use crate::Result;
+use crate::{Error, Result};
+use std::{path::Path, process::Command};
 
 /// Send notifications
 pub(crate) fn notify(title: &str, msg: &str) -> Result<()> {
@@ -8,6 +9,107 @@ pub(crate) fn notify(title: &str, msg: &str) -> Result<()> {
     Ok(())
 }
 
+/// Atomically replace the contents of `path` by writing a temporary file next
+/// to it and renaming it into place. If `path` is a symlink, the file it points
+/// to is replaced rather than the link itself.
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    use std::io::Write;
+
+    let target = if path
+        .symlink_metadata()
+        .is_ok_and(|m| m.file_type().is_symlink())
+    {
+        std::fs::canonicalize(path)?
+    } else {
+        path.to_owned()
+    };
+    let dir = target
+        .parent()
+        .ok_or_else(|| Error::BadPath(target.display().to_string()))?;
+
+    let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+    tmp.write_all(contents)?;
+    if let Ok(metadata) = std::fs::metadata(&target) {
+        tmp.as_file().set_permissions(metadata.permissions())?;
+    }
+    tmp.as_file().sync_all()?;
+    tmp.persist(&target).map_err(|e| e.error)?;
+
+    Ok(())
+}
+
+/// Run `cmd` in a session of its own, as the child of a process that exits
+/// right away. The app is adopted by init instead of staying a child of
+/// whoever started handlr, so it neither shares their session nor lingers as
+/// a zombie in a long-running parent like a file manager.
+#[allow(unsafe_code)]
+pub(crate) fn spawn_detached(cmd: &mut Command) -> Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    // SAFETY: only async-signal-safe functions are called between the fork
+    // and the exec
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            match libc::fork() {
+                -1 => Err(std::io::Error::last_os_error()),
+                0 => Ok(()),
+                _ => libc::_exit(0),
+            }
+        });
+    }
+
+    // Failing to exec is still reported, as the grandchild inherits the pipe
+    // `spawn` reads errors from
+    cmd.spawn()?.wait()?;
+
+    Ok(())
+}
+
+/// Desktop names listed in `$XDG_CURRENT_DESKTOP`, in order of precedence
+pub(crate) fn current_desktops() -> Vec<String> {
+    std::env::var("XDG_CURRENT_DESKTOP")
+        .map(|desktops| {
+            desktops
+                .split(':')
+                .filter(|d| !d.is_empty())
+                .map(ToOwned::to_owned)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Ask a question on the terminal, returning the default for an empty answer
+pub(crate) fn prompt(question: &str, default: Option<&str>) -> Result<String> {
+    match default {
+        Some(default) if !default.is_empty() => eprint!("{question} [{default}]: "),
+        _ => eprint!("{question}: "),
+    }
+
+    let mut input = String::new();
+    if std::io::stdin().read_line(&mut input)? == 0 {
+        return Err(Error::Cancelled);
+    }
+
+    match input.trim() {
+        "" => Ok(default.unwrap_or_default().to_owned()),
+        answer => Ok(answer.to_owned()),
+    }
+}
+
+/// Ask a yes/no question on the terminal
+pub(crate) fn confirm(question: &str, default: bool) -> Result<bool> {
+    let hint = if default { "Y/n" } else { "y/N" };
+    let answer = prompt(&format!("{question} ({hint})"), None)?;
+
+    Ok(match answer.to_lowercase().as_str() {
+        "" => default,
+        answer => answer.starts_with('y'),
+    })
+}
+
 /// Interactively select one of the given items within the TUI
 #[allow(unused)]
 pub(crate) fn select_item<'a, S: AsRef<str>>(prompt: &'a str, items: &'a [S]) -> Option<usize> {
@@ -174,11 +276,11 @@ pub(crate) mod skim {
         }
 
         fn text(&self) -> Cow<str> {
-            self.0.file_name.to_str().unwrap().replace("\"", "").into()
+            self.0.selector_id().into()
         }
 
         fn output(&self) -> Cow<str> {
-            self.0.file_name.to_str().unwrap().replace("\"", "").into()
+            self.0.selector_id().into()
         }
     }
 
@@ -218,8 +320,74 @@ pub(crate) mod skim {
         Some(
             desktops
                 .iter()
-                .find(|e| e.file_name.to_str().unwrap().replace("\"", "") == selected)
+                .find(|e| e.selector_id() == selected)
                 .unwrap(),
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn write_atomic_through_symlink() -> Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir()?;
+        let (target, link) = (
+            dir.path().join("dotfiles-mimeapps.list"),
+            dir.path().join("mimeapps.list"),
+        );
+        std::fs::write(&target, "old\n")?;
+        std::fs::set_permissions(&target, std::fs::Permissions::from_mode(0o600))?;
+        std::os::unix::fs::symlink(&target, &link)?;
+
+        write_atomic(&link, b"new\n")?;
+
+        assert!(link.symlink_metadata()?.file_type().is_symlink());
+        assert_eq!(std::fs::read_link(&link)?, target);
+        assert_eq!(std::fs::read_to_string(&target)?, "new\n");
+        assert_eq!(
+            std::fs::metadata(&target)?.permissions().mode() & 0o777,
+            0o600
+        );
+
+        Ok(())
+    }
+
+    /// The session of a process, from `/proc/<pid>/stat`
+    fn session(stat: &str) -> Option<&str> {
+        // The name of the command is in parentheses and may contain spaces
+        stat.rsplit_once(')')?.1.split_whitespace().nth(3)
+    }
+
+    #[test]
+    fn detached_session() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let out = dir.path().join("stat");
+
+        spawn_detached(
+            Command::new("sh")
+                .arg("-c")
+                .arg("cat /proc/$$/stat > \"$0.tmp\" && mv \"$0.tmp\" \"$0\"")
+                .arg(&out),
+        )?;
+
+        let mut tries = 0;
+        while !out.exists() && tries < 50 {
+            std::thread::sleep(Duration::from_millis(100));
+            tries += 1;
+        }
+
+        let child = std::fs::read_to_string(&out)?;
+        let own = std::fs::read_to_string("/proc/self/stat")?;
+        assert!(session(&child).is_some());
+        assert_ne!(session(&child), session(&own));
+
+        spawn_detached(&mut Command::new("handlr-test-not-installed")).unwrap_err();
+
+        Ok(())
+    }
+}